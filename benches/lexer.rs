@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use saffron::parsing::lex_lines;
+use saffron::{lex_all, lex_line};
+
+/// A document of `definitions` ordinary definitions, each with a signature,
+/// a comment and a body using most kinds of token
+fn code_document(definitions: usize) -> String {
+    let mut document = "module Bench.Lexer where\n\nimport Data.List (map, foldr)\n\n".to_string();
+    for i in 0..definitions {
+        document.push_str(&format!(
+            "-- | Adds up the {i}th pair\n\
+             add{i} : Int\n\
+             add{i} x y =\n  \
+               let z = x + y * {i} in\n  \
+               if z >= 0x1F then [z, x, 2.5e3] else map (\\w -> w - 1) [y, 'c']\n\n"
+        ));
+    }
+    document
+}
+
+/// A document made mostly of strings and comments, with escapes, multiline
+/// strings and nested block comments, which take the lexer's slowest paths
+fn text_document(definitions: usize) -> String {
+    let mut document = String::new();
+    for i in 0..definitions {
+        document.push_str(&format!(
+            "{{- block {i} {{- nested -}}\n   still a comment -}}\n\
+             greeting{i} = \"hello\\t\\\"world\\\" 😀 {i}\" -- trailing note\n\
+             poem{i} = \"\"\"\n  roses are red\n  \"quoted\" lines {i}\n\"\"\"\n\n"
+        ));
+    }
+    document
+}
+
+fn lexing(c: &mut Criterion) {
+    let documents = [
+        ("small", code_document(10)),
+        ("medium", code_document(1_000)),
+        ("large", code_document(20_000)),
+        ("strings and comments", text_document(5_000)),
+    ];
+    let mut group = c.benchmark_group("lexing");
+    for (name, document) in &documents {
+        group.throughput(Throughput::Bytes(document.len() as u64));
+        group.bench_with_input(BenchmarkId::new("lex_line", name), document, |b, d| {
+            b.iter(|| lex_line(black_box(d)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("lex_lines", name), document, |b, d| {
+            b.iter(|| lex_lines(black_box(d)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("lex_all", name), document, |b, d| {
+            b.iter(|| lex_all(black_box(d)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexing);
+criterion_main!(benches);
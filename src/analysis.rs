@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::diagnostic::{Diagnostic, DiagnosticSeverity, DiagnosticTag};
+use crate::package::{Module, ModuleReference};
+use crate::parsing::{Expr, Pattern};
+use crate::types;
+
+/// Parses a whole source file and runs every check on it, giving back as
+/// much of the module as could be parsed along with all the diagnostics
+/// found, in document order. It never fails: broken declarations are
+/// reported and left out of the module.
+pub fn parse_module(text: &str) -> (Module, Vec<Diagnostic>) {
+    let (module, mut diagnostics) = Module::parse(text);
+    for check in Check::ALL {
+        diagnostics.extend(check.run(&module));
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    (module, diagnostics)
+}
+
+/// One of the checks run over a parsed module
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Check {
+    Duplicates,
+    Undefined,
+    UnusedImports,
+    Types,
+}
+
+impl Check {
+    pub const ALL: [Check; 4] = [Check::Duplicates, Check::Undefined, Check::UnusedImports, Check::Types];
+
+    /// The problems this check finds in `module`
+    pub fn run(self, module: &Module) -> Vec<Diagnostic> {
+        match self {
+            Check::Duplicates => check_duplicates(module),
+            Check::Undefined => check_undefined(module),
+            Check::UnusedImports => check_unused_imports(module),
+            Check::Types => types::infer_module(module).1,
+        }
+    }
+}
+
+/// Reports every top-level definition whose name was already defined
+/// earlier in the module, pointing back at the first definition
+pub fn check_duplicates(module: &Module) -> Vec<Diagnostic> {
+    let mut first = HashMap::new();
+    let mut diagnostics = vec![];
+    for definition in &module.members {
+        match first.get(definition.name.as_str()) {
+            None => {
+                first.insert(definition.name.as_str(), definition.name_span.clone());
+            }
+            Some(original) => {
+                let mut diagnostic = Diagnostic::error(
+                    definition.name_span.clone(),
+                    format!("`{}` is already defined", definition.name),
+                );
+                diagnostic.related.push((original.clone(), format!("`{}` is first defined here", definition.name)));
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+    diagnostics
+}
+
+/// The names visible to a module's definitions, besides its local bindings
+struct Globals<'a> {
+    defined: Vec<&'a str>,
+    imported: Vec<&'a str>,
+    /// Qualifiers that can start a qualified name, like `Data.List` in
+    /// `Data.List.map`
+    modules: Vec<&'a str>,
+    /// Whether something is imported wholesale, so any name could come from
+    /// it
+    wildcard: bool,
+}
+
+/// Reports every variable that isn't bound by an enclosing lambda or `let`,
+/// defined at the top of the module, or imported. Top-level definitions can
+/// refer to each other in any order. Constructors aren't checked, since
+/// nothing declares them yet.
+pub fn check_undefined(module: &Module) -> Vec<Diagnostic> {
+    let mut globals = Globals {
+        defined: module.members.iter().map(|d| d.name.as_str()).collect(),
+        imported: vec![],
+        modules: vec![module.name.as_str()],
+        wildcard: false,
+    };
+    for import in &module.imports {
+        globals.modules.push(&import.name.value);
+        match &import.reference {
+            ModuleReference::WildCard => globals.wildcard = true,
+            ModuleReference::Single(name) => globals.imported.push(&name.name),
+            ModuleReference::Many(names) => globals.imported.extend(names.iter().map(|n| n.name.as_str())),
+        }
+    }
+    free_variables(module)
+        .into_iter()
+        .filter(|(name, _)| match name.rsplit_once('.') {
+            Some((qualifier, _)) => !globals.modules.contains(&qualifier),
+            None => {
+                !name.starts_with(|c: char| c.is_uppercase())
+                    && !globals.defined.contains(name)
+                    && !globals.imported.contains(name)
+                    && !globals.wildcard
+            }
+        })
+        .map(|(name, span)| Diagnostic::error(span.clone(), format!("`{}` isn't defined", name)))
+        .collect()
+}
+
+/// Hints at every name imported by `import Foo (bar, baz)` that the module
+/// never refers to. A wildcard `import Foo` can't be shown to be unused, so
+/// it's left alone.
+pub fn check_unused_imports(module: &Module) -> Vec<Diagnostic> {
+    let used: Vec<&str> = free_variables(module).into_iter().map(|(name, _)| name).collect();
+    module
+        .imports
+        .iter()
+        .flat_map(|import| match &import.reference {
+            ModuleReference::WildCard => vec![],
+            ModuleReference::Single(name) => vec![name],
+            ModuleReference::Many(names) => names.iter().collect(),
+        })
+        .filter(|imported| !used.contains(&imported.name.as_str()))
+        .map(|imported| Diagnostic {
+            tags: vec![DiagnosticTag::UNNECESSARY],
+            ..Diagnostic::new(
+                imported.span.clone(),
+                DiagnosticSeverity::HINT,
+                format!("`{}` is imported but never used", imported.name),
+            )
+        })
+        .collect()
+}
+
+/// Every variable the module's definitions refer to that isn't bound by an
+/// enclosing lambda or `let`, in order
+fn free_variables(module: &Module) -> Vec<(&str, &Range<usize>)> {
+    let mut found = vec![];
+    for definition in &module.members {
+        free_in(&definition.def_expr, &mut vec![], &mut |name, span, _| found.push((name, span)));
+    }
+    found
+}
+
+/// The local binding of `new_name` that some use of the free variable `name`
+/// sits inside, so renaming `name` to `new_name` would make that use refer
+/// to the binding instead. Points at the binding's name.
+pub fn rename_capture(module: &Module, name: &str, new_name: &str) -> Option<Range<usize>> {
+    let mut capture = None;
+    for definition in &module.members {
+        free_in(&definition.def_expr, &mut vec![], &mut |free, _, locals| {
+            if free == name && capture.is_none() {
+                capture = locals.iter().rev().find(|(local, _)| *local == new_name).map(|(_, span)| (*span).clone());
+            }
+        });
+    }
+    capture
+}
+
+/// How many arguments the variable at `span` is applied to in `expr`, so
+/// two for `f` in `f a b` and none for `f` in `g f`. `None` when there's no
+/// variable at `span` at all.
+pub fn arguments_applied(expr: &Expr, span: &Range<usize>) -> Option<usize> {
+    match expr {
+        Expr::IntLit(..) | Expr::FloatLit(..) | Expr::StringLit(..) => None,
+        Expr::Var(_, at) => (at == span).then_some(0),
+        Expr::List(elements, _) | Expr::Tuple(elements, _) => {
+            elements.iter().find_map(|element| arguments_applied(element, span))
+        }
+        Expr::App(..) => {
+            // the arguments are counted back from the end of the chain to the
+            // function at its head
+            let mut head = expr;
+            let mut args = vec![];
+            while let Expr::App(func, arg) = head {
+                args.push(&**arg);
+                head = func;
+            }
+            match head {
+                Expr::Var(_, at) if at == span => Some(args.len()),
+                _ => std::iter::once(head).chain(args).find_map(|e| arguments_applied(e, span)),
+            }
+        }
+        Expr::BinOp(_, lhs, rhs) => arguments_applied(lhs, span).or_else(|| arguments_applied(rhs, span)),
+        Expr::If { cond, then_branch, else_branch, .. } => [cond, then_branch, else_branch]
+            .into_iter()
+            .find_map(|e| arguments_applied(e, span)),
+        Expr::Case { scrutinee, arms, .. } => arguments_applied(scrutinee, span)
+            .or_else(|| arms.iter().find_map(|(_, body)| arguments_applied(body, span))),
+        Expr::Lam { body, .. } => arguments_applied(body, span),
+        Expr::Let { bound, body, .. } => arguments_applied(bound, span).or_else(|| arguments_applied(body, span)),
+        Expr::Where { body, bindings } => arguments_applied(body, span)
+            .or_else(|| bindings.iter().find_map(|binding| arguments_applied(&binding.def_expr, span))),
+    }
+}
+
+/// A variable bound inside a definition, and where it's bound
+type Local<'a> = (&'a str, &'a Range<usize>);
+
+/// Calls `found` with every variable in `expr` that isn't one of `locals` or
+/// bound inside it, along with the locals in scope there, innermost last
+fn free_in<'a>(expr: &'a Expr, locals: &mut Vec<Local<'a>>, found: &mut impl FnMut(&'a str, &'a Range<usize>, &[Local<'a>])) {
+    names_in(expr, locals, &mut |name, span, locals| {
+        if !locals.iter().any(|(local, _)| *local == name) {
+            found(name, span, locals);
+        }
+    });
+}
+
+/// Every local binding in the module, in order, as the span of the name
+/// it binds along with that span and those of every use of it
+pub fn local_bindings(module: &Module) -> Vec<(Range<usize>, Vec<Range<usize>>)> {
+    let mut bindings: Vec<(Range<usize>, Vec<Range<usize>>)> = vec![];
+    for definition in &module.members {
+        names_in(&definition.def_expr, &mut vec![], &mut |name, span, locals| {
+            if let Some((_, binder)) = locals.iter().rev().find(|(local, _)| *local == name) {
+                match bindings.iter_mut().find(|(bound, _)| bound == *binder) {
+                    Some((_, uses)) => uses.push(span.clone()),
+                    None => bindings.push(((*binder).clone(), vec![span.clone()])),
+                }
+            }
+        });
+    }
+    bindings
+}
+
+/// Calls `found` with every name in `expr`, both variables and the names
+/// bound by lambdas, `let`s, `case` arms and `where` bindings, along with
+/// the locals in scope there, innermost last. A bound name is in scope at
+/// itself.
+fn names_in<'a>(expr: &'a Expr, locals: &mut Vec<Local<'a>>, found: &mut impl FnMut(&'a str, &'a Range<usize>, &[Local<'a>])) {
+    match expr {
+        Expr::IntLit(..) | Expr::FloatLit(..) | Expr::StringLit(..) => {}
+        Expr::Var(name, span) => found(name, span, locals),
+        Expr::List(elements, _) | Expr::Tuple(elements, _) => {
+            for element in elements {
+                names_in(element, locals, found);
+            }
+        }
+        Expr::App(lhs, rhs) | Expr::BinOp(_, lhs, rhs) => {
+            names_in(lhs, locals, found);
+            names_in(rhs, locals, found);
+        }
+        Expr::If { cond, then_branch, else_branch, .. } => {
+            names_in(cond, locals, found);
+            names_in(then_branch, locals, found);
+            names_in(else_branch, locals, found);
+        }
+        Expr::Case { scrutinee, arms, .. } => {
+            names_in(scrutinee, locals, found);
+            for (pattern, body) in arms {
+                match pattern {
+                    Pattern::Var(name, span) => {
+                        locals.push((name, span));
+                        found(name, span, locals);
+                        names_in(body, locals, found);
+                        locals.pop();
+                    }
+                    _ => names_in(body, locals, found),
+                }
+            }
+        }
+        Expr::Lam { param, param_span, body, .. } => {
+            locals.push((param, param_span));
+            found(param, param_span, locals);
+            names_in(body, locals, found);
+            locals.pop();
+        }
+        Expr::Let { name, name_span, bound, body, .. } => {
+            // bindings can refer to themselves
+            locals.push((name, name_span));
+            found(name, name_span, locals);
+            names_in(bound, locals, found);
+            names_in(body, locals, found);
+            locals.pop();
+        }
+        Expr::Where { body, bindings } => {
+            // the bindings can refer to each other and to themselves
+            let outer = locals.len();
+            locals.extend(bindings.iter().map(|b| (b.name.as_str(), &b.name_span)));
+            for binding in bindings {
+                found(&binding.name, &binding.name_span, locals);
+                names_in(&binding.def_expr, locals, found);
+            }
+            names_in(body, locals, found);
+            locals.truncate(outer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse_top_level;
+
+    fn module(input: &str) -> Module {
+        Module::from_declarations(parse_top_level(input).unwrap()).0
+    }
+
+    #[test]
+    fn can_parse_a_whole_module() {
+        let (module, diagnostics) = parse_module(
+            "module Shapes (area) where\n\nimport Data.List (map)\n\narea : Int\narea = square 3\n\nsquare : Int\nsquare x = map x\n",
+        );
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(module.name, "Shapes");
+        assert_eq!(module.imports[0].name.value, "Data.List");
+        let members: Vec<_> = module.members.iter().map(|d| (d.name.as_str(), d.def_type.is_some())).collect();
+        assert_eq!(members, vec![("area", true), ("square", true)]);
+    }
+
+    #[test]
+    fn parsing_a_module_runs_every_check() {
+        let (module, diagnostics) = parse_module("main : Int\nmain = missing 1\nmain = 2\nbroken = )\n");
+        assert_eq!(module.members.len(), 2);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["`missing` isn't defined", "`main` has no type signature", "`main` is already defined", "expected an expression"]
+        );
+    }
+
+    #[test]
+    fn duplicate_definition_points_at_the_first() {
+        let diagnostics = check_duplicates(&module("answer = 42\nzero = 0\nanswer = 43"));
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                related: vec![(0..6, "`answer` is first defined here".to_string())],
+                ..Diagnostic::error(21..27, "`answer` is already defined")
+            }]
+        );
+    }
+
+    #[test]
+    fn undefined_reference_is_reported() {
+        // `double` is defined after it's used, which is fine
+        assert_eq!(
+            check_undefined(&module("answer = double 21\ndouble = \\x -> x * y")),
+            vec![Diagnostic::error(38..39, "`y` isn't defined")]
+        );
+    }
+
+    #[test]
+    fn local_bindings_are_scoped() {
+        assert_eq!(
+            check_undefined(&module("f = \\x -> let y = x in y\ng = y")),
+            vec![Diagnostic::error(29..30, "`y` isn't defined")]
+        );
+    }
+
+    #[test]
+    fn imported_names_are_defined() {
+        assert_eq!(
+            check_undefined(&module("import Foo (bar)\nx = bar baz Foo.qux Other.thing")),
+            vec![
+                Diagnostic::error(25..28, "`baz` isn't defined"),
+                Diagnostic::error(37..48, "`Other.thing` isn't defined"),
+            ]
+        );
+        assert_eq!(check_undefined(&module("import Foo\nx = bar baz")), vec![]);
+    }
+
+    #[test]
+    fn unused_import_is_hinted() {
+        assert_eq!(
+            check_unused_imports(&module("import Foo (bar, baz)\nimport Bar\nx = bar 1")),
+            vec![Diagnostic {
+                tags: vec![DiagnosticTag::UNNECESSARY],
+                ..Diagnostic::new(17..20, DiagnosticSeverity::HINT, "`baz` is imported but never used")
+            }]
+        );
+    }
+
+    #[test]
+    fn shadowed_import_is_unused() {
+        let diagnostics = check_unused_imports(&module("import Foo (bar)\nx = \\bar -> bar"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, 12..15);
+    }
+
+    #[test]
+    fn where_bindings_are_local_to_their_definition() {
+        let module = module("f x = y + z\n  where\n    y = x + 1\n    z = y\ng = y\n");
+        let names: Vec<_> = module.members.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["f", "g"]);
+        assert_eq!(check_undefined(&module), vec![Diagnostic::error(48..49, "`y` isn't defined")]);
+    }
+
+    #[test]
+    fn renaming_into_a_local_binding_is_a_capture() {
+        let module = module("x = 1\nf = let y = 2 in x + y\ng = \\z -> x\n");
+        assert_eq!(rename_capture(&module, "x", "y"), Some(14..15));
+        assert_eq!(rename_capture(&module, "x", "z"), Some(34..35));
+        assert_eq!(rename_capture(&module, "x", "w"), None);
+        // a use of `y` that's local already isn't the top-level one
+        assert_eq!(rename_capture(&module, "y", "x"), None);
+    }
+
+    #[test]
+    fn uses_belong_to_the_innermost_binding() {
+        let module = module("x = 1\nf x = x\ng = \\x -> (x, let x = 2 in x)\n");
+        assert_eq!(
+            local_bindings(&module),
+            vec![(8..9, vec![8..9, 12..13]), (19..20, vec![19..20, 25..26]), (32..33, vec![32..33, 41..42])]
+        );
+    }
+
+    #[test]
+    fn arguments_are_counted_from_the_head_of_an_application() {
+        let module = module("x = f 1 (g f) + f\n");
+        let body = &module.members[0].def_expr;
+        assert_eq!(arguments_applied(body, &(4..5)), Some(2));
+        assert_eq!(arguments_applied(body, &(9..10)), Some(1));
+        assert_eq!(arguments_applied(body, &(11..12)), Some(0));
+        assert_eq!(arguments_applied(body, &(16..17)), Some(0));
+        assert_eq!(arguments_applied(body, &(6..7)), None);
+    }
+
+    #[test]
+    fn distinct_definitions_are_fine() {
+        assert_eq!(check_duplicates(&module("answer = 42\nzero = 0")), vec![]);
+    }
+}
@@ -0,0 +1,53 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+pub use tower_lsp::lsp_types::{DiagnosticSeverity, DiagnosticTag};
+
+use crate::parsing::ParseError;
+
+/// A problem found while parsing or checking a document, located by byte
+/// offsets so it can be reported without the document's line structure
+#[derive(PartialEq, Clone, Debug)]
+pub struct Diagnostic {
+    pub span: Range<usize>,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    /// Other places in the document that help explain the problem
+    pub related: Vec<(Range<usize>, String)>,
+    pub tags: Vec<DiagnosticTag>,
+    /// The file the problem is in, when it's found while loading several
+    pub path: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn new(span: Range<usize>, severity: DiagnosticSeverity, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            span,
+            severity,
+            message: message.into(),
+            related: vec![],
+            tags: vec![],
+            path: None,
+        }
+    }
+
+    pub fn error(span: Range<usize>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(span, DiagnosticSeverity::ERROR, message)
+    }
+
+    pub fn warning(span: Range<usize>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(span, DiagnosticSeverity::WARNING, message)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Diagnostic {
+        let start = error.offset().unwrap_or(0);
+        let end = match error {
+            ParseError::UnexpectedChar { found, .. } => start + found.len_utf8(),
+            ParseError::Expected { len, .. } => start + len,
+            ParseError::Wrong => start,
+        };
+        Diagnostic::error(start..end, error.to_string())
+    }
+}
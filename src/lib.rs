@@ -0,0 +1,19 @@
+//! Lexing and parsing for the Saffron language, along with the package
+//! structure that parsed modules are collected into. The language server in
+//! `main.rs` is built on top of this crate.
+
+pub mod analysis;
+pub mod diagnostic;
+pub mod package;
+pub mod parsing;
+pub mod position;
+pub mod pretty;
+pub mod types;
+
+pub use analysis::{parse_module, Check};
+pub use diagnostic::Diagnostic;
+pub use package::{load_package, module_path, package_root, Definition, Import, ImportedName, Module, ModuleName, ModuleReference, Package};
+#[cfg(feature = "serde")]
+pub use package::dump_ast;
+pub use parsing::{lex_all, lex_iter, lex_line, lex_line_resilient, Lexer, ParseError, Spanned, Token, TokenContent};
+pub use position::{tokenize_document, PositionedToken};
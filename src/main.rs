@@ -1,42 +1,1319 @@
-use std::cell::Cell;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
-use tower_lsp::jsonrpc::Result;
+use std::sync::OnceLock;
+use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 
-mod parsing;
+use saffron::{analysis, parsing, types, Check};
+use saffron::position::{line_col_to_offset, offset_to_position};
+use saffron::{tokenize_document, ModuleReference, ParseError, PositionedToken, Token, TokenContent};
+use self_cell::self_cell;
 
+/// What a top-level name of a document stands for
+#[derive(PartialEq, Clone, Debug)]
 enum Value {
-    Str(String),
-    Sym(String),
+    /// A definition without parameters
+    Constant,
+    /// A function, with the names of its parameters
+    Function(Vec<String>),
 }
 
+/// The top-level names of one document, read again each time it changes so
+/// hover and completion don't have to
+#[derive(PartialEq, Default, Debug)]
 struct Module {
     values: HashMap<String, Value>,
+    /// The names in the order they're first defined
+    names: Vec<String>,
+}
+
+impl Module {
+    /// The definitions of `text`. Bad characters don't stop the lexing, so a
+    /// typo in one definition doesn't hide the others.
+    fn new(text: &str) -> Module {
+        let (tokens, _) = parsing::lex_line_resilient(text);
+        let mut module = Module::default();
+        for site in parsing::top_level_definitions(&tokens) {
+            let name = &text[site.name.start..site.name.end];
+            // a name defined twice is described by its first definition
+            if module.values.contains_key(name) {
+                continue;
+            }
+            let value = if site.params.is_empty() {
+                Value::Constant
+            } else {
+                Value::Function(site.params.iter().map(|p| text[p.start..p.end].to_string()).collect())
+            };
+            module.values.insert(name.to_string(), value);
+            module.names.push(name.to_string());
+        }
+        module
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    fn completions(&self) -> Vec<CompletionItem> {
+        self.names
+            .iter()
+            .map(|name| CompletionItem {
+                label: name.clone(),
+                kind: Some(match self.values[name] {
+                    Value::Constant => CompletionItemKind::CONSTANT,
+                    Value::Function(_) => CompletionItemKind::FUNCTION,
+                }),
+                ..CompletionItem::default()
+            })
+            .collect()
+    }
+}
+
+/// Finds the token of `text` under a zero-based line and character
+fn token_at<'a, 'doc>(text: &str, tokens: &'a [Token<'doc>], line: u32, character: u32) -> Option<&'a Token<'doc>> {
+    let offset = line_col_to_offset(text, line, character);
+    tokens.iter().find(|t| t.start <= offset && offset < t.end)
+}
+
+/// Replaces the text covered by an LSP `range` with `new_text`
+fn apply_edit(text: &mut String, range: Range, new_text: &str) {
+    let start = line_col_to_offset(text, range.start.line, range.start.character);
+    let end = line_col_to_offset(text, range.end.line, range.end.character);
+    text.replace_range(start..end.max(start), new_text);
+}
+
+/// Applies a `did_change` event, which replaces the whole document when
+/// it has no range
+fn apply_change(text: &mut String, change: &TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => apply_edit(text, range, &change.text),
+        None => *text = change.text.clone(),
+    }
+}
+
+/// The diagnostics from lexing a whole document, one for every character
+/// that can't be lexed
+fn lex_diagnostics(text: &str, uri: &Url) -> Vec<Diagnostic> {
+    let (_, errors) = parsing::lex_line_resilient(text);
+    errors.iter().map(|e| error_diagnostic(text, uri, e)).collect()
+}
+
+fn error_diagnostic(text: &str, uri: &Url, error: &ParseError) -> Diagnostic {
+    lsp_diagnostic(text, uri, &saffron::Diagnostic::from(error))
+}
+
+/// Places a diagnostic found by the library into the document at `uri`,
+/// along with the other places in it that explain the problem
+fn lsp_diagnostic(text: &str, uri: &Url, diagnostic: &saffron::Diagnostic) -> Diagnostic {
+    let range = |span: &std::ops::Range<usize>| {
+        Range::new(offset_to_position(text, span.start), offset_to_position(text, span.end))
+    };
+    let related = diagnostic
+        .related
+        .iter()
+        .map(|(span, message)| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), range(span)),
+            message: message.clone(),
+        })
+        .collect::<Vec<_>>();
+    Diagnostic {
+        range: range(&diagnostic.span),
+        severity: Some(diagnostic.severity),
+        source: Some("saffron".to_string()),
+        message: diagnostic.message.clone(),
+        related_information: (!related.is_empty()).then_some(related),
+        tags: (!diagnostic.tags.is_empty()).then(|| diagnostic.tags.clone()),
+        ..Diagnostic::default()
+    }
+}
+
+/// An outline entry for each top-level definition
+#[allow(deprecated)]
+fn document_symbols(text: &str, tokens: &[Token]) -> Vec<DocumentSymbol> {
+    parsing::top_level_definitions(tokens)
+        .into_iter()
+        .map(|site| DocumentSymbol {
+            name: text[site.name.start..site.name.end].to_string(),
+            detail: None,
+            kind: if site.params.is_empty() {
+                SymbolKind::CONSTANT
+            } else {
+                SymbolKind::FUNCTION
+            },
+            tags: None,
+            deprecated: None,
+            range: Range::new(offset_to_position(text, site.start), offset_to_position(text, site.end)),
+            selection_range: site.name.lsp_range(text),
+            children: None,
+        })
+        .collect()
+}
+
+/// Where the identifier under the cursor is defined: at its local binding
+/// when it has one, and otherwise at the top level
+fn definition_range(
+    text: &str,
+    tokens: &[Token],
+    module: &saffron::Module,
+    line: u32,
+    character: u32,
+) -> Option<Range> {
+    let token = token_at(text, tokens, line, character)?;
+    let TokenContent::Ident(name) = token.content else {
+        return None;
+    };
+    let bindings = analysis::local_bindings(module);
+    if let Some((binder, _)) = bindings.iter().find(|(_, uses)| uses.contains(&(token.start..token.end))) {
+        return Some(Range::new(offset_to_position(text, binder.start), offset_to_position(text, binder.end)));
+    }
+    parsing::top_level_definitions(tokens)
+        .into_iter()
+        .find(|site| site.name.content == TokenContent::Ident(name))
+        .map(|site| site.name.lsp_range(text))
+}
+
+/// Every occurrence of the identifier under the cursor that refers to the
+/// same thing, optionally leaving out where it's defined. A local binding
+/// only reaches its own uses, and a top-level name skips the locals that
+/// shadow it.
+fn references<'a, 'doc>(
+    text: &str,
+    tokens: &'a [Token<'doc>],
+    module: &saffron::Module,
+    line: u32,
+    character: u32,
+    include_declaration: bool,
+) -> Vec<&'a Token<'doc>> {
+    let Some(token) = token_at(text, tokens, line, character) else {
+        return vec![];
+    };
+    let TokenContent::Ident(name) = token.content else {
+        return vec![];
+    };
+    let bindings = analysis::local_bindings(module);
+    if let Some((binder, uses)) = bindings.iter().find(|(_, uses)| uses.contains(&(token.start..token.end))) {
+        return tokens
+            .iter()
+            .filter(|t| uses.contains(&(t.start..t.end)))
+            .filter(|t| include_declaration || (t.start..t.end) != *binder)
+            .collect();
+    }
+    let declarations: Vec<usize> = parsing::top_level_definitions(tokens)
+        .iter()
+        .map(|site| site.name.start)
+        .collect();
+    tokens
+        .iter()
+        .filter(|t| t.content == TokenContent::Ident(name))
+        .filter(|t| !bindings.iter().any(|(_, uses)| uses.contains(&(t.start..t.end))))
+        .filter(|t| include_declaration || !declarations.contains(&t.start))
+        .collect()
+}
+
+/// Why `token` can't be renamed, when it's a local binding or a use of one
+fn local_rename_refusal(module: &saffron::Module, token: &Token) -> Option<Error> {
+    let TokenContent::Ident(name) = token.content else {
+        return None;
+    };
+    analysis::local_bindings(module)
+        .iter()
+        .any(|(_, uses)| uses.contains(&(token.start..token.end)))
+        .then(|| Error::invalid_params(format!("`{}` is local, and only top-level definitions can be renamed", name)))
+}
+
+/// Edits renaming every occurrence of the top-level name under the cursor.
+/// The rename is refused for local names, when the new name is already
+/// defined at the top level, and if a local binding of the new name would
+/// capture one of the uses.
+fn rename_edits(
+    text: &str,
+    tokens: &[Token],
+    module: &saffron::Module,
+    line: u32,
+    character: u32,
+    new_name: &str,
+) -> Result<Vec<TextEdit>> {
+    if !parsing::is_identifier(new_name) {
+        return Err(Error::invalid_params(format!("`{}` isn't a valid identifier", new_name)));
+    }
+    if let Some(refusal) = token_at(text, tokens, line, character).and_then(|t| local_rename_refusal(module, t)) {
+        return Err(refusal);
+    }
+    if let Some(TokenContent::Ident(name)) = token_at(text, tokens, line, character).map(|t| &t.content) {
+        let clash = module.members.iter().find(|definition| definition.name == new_name && *name != new_name);
+        if let Some(definition) = clash {
+            return Err(Error::invalid_params(format!(
+                "`{}` is already defined at {}",
+                new_name,
+                location(text, definition.name_span.start)
+            )));
+        }
+        if let Some(binding) = analysis::rename_capture(module, name, new_name) {
+            return Err(Error::invalid_params(format!(
+                "renaming `{}` to `{}` would capture it in the `{}` bound at {}",
+                name,
+                new_name,
+                new_name,
+                location(text, binding.start)
+            )));
+        }
+    }
+    Ok(references(text, tokens, module, line, character, true)
+        .into_iter()
+        .map(|t| TextEdit::new(t.lsp_range(text), new_name.to_string()))
+        .collect())
+}
+
+/// The range of the name a rename at the cursor would change. Only
+/// top-level names can be renamed; a cursor just after one still counts as
+/// on it.
+fn prepare_rename_range(text: &str, tokens: &[Token], module: &saffron::Module, line: u32, character: u32) -> Result<Range> {
+    let offset = line_col_to_offset(text, line, character);
+    let token = tokens
+        .iter()
+        .find(|t| matches!(t.content, TokenContent::Ident(_)) && t.start <= offset && offset <= t.end)
+        .ok_or_else(|| Error::invalid_params("only names can be renamed"))?;
+    match local_rename_refusal(module, token) {
+        Some(refusal) => Err(refusal),
+        None => Ok(token.lsp_range(text)),
+    }
+}
+
+/// Highlights every occurrence of the identifier under the cursor, marking
+/// the definition as a write and everything else as a read
+fn document_highlights(
+    text: &str,
+    tokens: &[Token],
+    module: &saffron::Module,
+    line: u32,
+    character: u32,
+) -> Vec<DocumentHighlight> {
+    let declarations: Vec<usize> = parsing::top_level_definitions(tokens)
+        .iter()
+        .map(|site| site.name.start)
+        .collect();
+    references(text, tokens, module, line, character, true)
+        .into_iter()
+        .map(|t| DocumentHighlight {
+            range: t.lsp_range(text),
+            kind: Some(if declarations.contains(&t.start) {
+                DocumentHighlightKind::WRITE
+            } else {
+                DocumentHighlightKind::READ
+            }),
+        })
+        .collect()
+}
+
+/// Completions for the cursor position. Straight after `Foo.` only the
+/// members of that module are offered, which for now means this
+/// document's own definitions, `symbols`, when `Foo` is its module name.
+fn completions(text: &str, tokens: &[Token], symbols: &Module, line: u32, character: u32) -> Vec<CompletionItem> {
+    let keywords = parsing::KEYWORDS.iter().map(|k| CompletionItem {
+        label: k.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..CompletionItem::default()
+    });
+    let offset = line_col_to_offset(text, line, character);
+    let before: Vec<_> = tokens
+        .iter()
+        .take_while(|t| t.end <= offset && t.content != TokenContent::Eof)
+        .collect();
+    if let [.., qualifier, dot] = before[..] {
+        if dot.content == TokenContent::Dot && dot.end == offset && qualifier.end == dot.start {
+            let qualifier = match &qualifier.content {
+                TokenContent::Constructor(name) => Some(name.to_string()),
+                TokenContent::QualifiedName(segments) => Some(segments.join(".")),
+                _ => None,
+            };
+            if qualifier.is_some() {
+                return if qualifier == parsing::header_module_name(tokens) {
+                    symbols.completions()
+                } else {
+                    vec![]
+                };
+            }
+        }
+    }
+    symbols.completions().into_iter().chain(keywords).collect()
+}
+
+/// The comments on the lines straight above the first line starting with
+/// `name`, which is its signature when it has one, with the comment markers
+/// left off
+fn doc_comment(tokens: &[Token], name: &str) -> Option<String> {
+    let lines: Vec<_> = tokens.split(|t| t.content == TokenContent::Newline).collect();
+    let mut top = lines
+        .iter()
+        .position(|line| matches!(line.first(), Some(Token { content: TokenContent::Ident(n), .. }) if *n == name))?;
+    let mut comments = vec![];
+    while top > 0 {
+        let mut significant = lines[top - 1]
+            .iter()
+            .filter(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_)));
+        match (significant.next().map(|t| &t.content), significant.next()) {
+            (Some(TokenContent::LineComment(c) | TokenContent::BlockComment(c)), None) => comments.push(c.trim()),
+            _ => break,
+        }
+        top -= 1;
+    }
+    comments.reverse();
+    (!comments.is_empty()).then(|| comments.join("\n"))
+}
+
+/// The inferred type of the function `name`, to follow its description on
+/// hover. When the use at `span` applies it to some of its arguments, this
+/// also says how many more it expects and the type of the next one.
+fn function_type_note(analysed: &Analysed, name: &str, span: &std::ops::Range<usize>) -> Option<String> {
+    let ty = analysed.type_of(name)?;
+    let mut note = format!(" of type `{}`", ty);
+    let params = ty.params();
+    let applied = analysed.module.members.iter().find_map(|definition| analysis::arguments_applied(&definition.def_expr, span));
+    if let Some(applied @ 1..) = applied {
+        match &params[applied.min(params.len())..] {
+            [] => {}
+            [next] => note.push_str(&format!("; here it expects 1 more argument, of type `{}`", next)),
+            [next, ..] => note.push_str(&format!(
+                "; here it expects {} more arguments, the next of type `{}`",
+                params.len() - applied,
+                next
+            )),
+        }
+    }
+    Some(note)
+}
+
+/// Fills in the type and documentation of a completion for one of the
+/// document's definitions. Anything else is handed back as it came.
+fn resolve_completion(tokens: &[Token], analysed: &Analysed, symbols: &Module, item: CompletionItem) -> CompletionItem {
+    if symbols.get(&item.label).is_none() {
+        return item;
+    }
+    CompletionItem {
+        detail: analysed.type_of(&item.label).map(|ty| ty.to_string()),
+        documentation: doc_comment(tokens, &item.label).map(Documentation::String),
+        ..item
+    }
+}
+
+fn keyword_description(keyword: &TokenContent) -> Option<&'static str> {
+    match keyword {
+        TokenContent::Module => Some("`module` names the module defined by this file"),
+        TokenContent::Where => Some("`where` starts a block of definitions"),
+        TokenContent::Import => Some("`import` brings another module's definitions into scope"),
+        TokenContent::Let => Some("`let` introduces local bindings"),
+        TokenContent::In => Some("`in` ends the bindings of a `let`"),
+        TokenContent::If => Some("`if` chooses between two expressions"),
+        TokenContent::Then => Some("`then` starts the branch taken when the condition holds"),
+        TokenContent::Else => Some("`else` starts the branch taken when the condition doesn't hold"),
+        TokenContent::Case => Some("`case` matches a value against patterns"),
+        TokenContent::Of => Some("`of` starts the arms of a `case`"),
+        TokenContent::Infixl => Some("`infixl` declares a left-associative operator and its precedence"),
+        TokenContent::Infixr => Some("`infixr` declares a right-associative operator and its precedence"),
+        TokenContent::Infix => Some("`infix` declares an operator that can't be chained, and its precedence"),
+        _ => None,
+    }
+}
+
+/// A description of the symbol under the cursor and the range it covers.
+/// Names bound locally are described as such, the rest are looked up in
+/// `symbols`, the document's definitions, and functions are described with
+/// their types from `analysed`.
+fn hover_message(
+    text: &str,
+    tokens: &[Token],
+    analysed: &Analysed,
+    symbols: &Module,
+    line: u32,
+    character: u32,
+) -> Option<(HoverText, Range)> {
+    let token = token_at(text, tokens, line, character)?;
+    let source = &text[token.start..token.end];
+    let local = || {
+        let span = token.start..token.end;
+        analysis::local_bindings(&analysed.module).iter().any(|(_, uses)| uses.contains(&span))
+    };
+    let message = match token.content {
+        TokenContent::Ident(name) if local() => {
+            HoverText { code: name.to_string(), description: format!("`{}` is a local binding", name) }
+        }
+        TokenContent::Ident(name) => match symbols.get(name)? {
+            Value::Constant => {
+                HoverText { code: name.to_string(), description: format!("`{}` is a top-level definition", name) }
+            }
+            Value::Function(params) => {
+                let code = format!("{} {}", name, params.join(" "));
+                let typed = function_type_note(analysed, name, &(token.start..token.end)).unwrap_or_default();
+                HoverText { description: format!("`{}` is a top-level function{}", code, typed), code }
+            }
+        },
+        TokenContent::String(_) | TokenContent::MultilineString(_) => HoverText {
+            code: format!("{} : String", source),
+            description: "a string literal".to_string(),
+        },
+        ref keyword => HoverText { code: source.to_string(), description: keyword_description(keyword)?.to_string() },
+    };
+    Some((message, token.lsp_range(text)))
+}
+
+/// What hovering over a token shows: its source, along with its type when
+/// that's known, and a sentence about it
+#[derive(PartialEq, Debug)]
+struct HoverText {
+    code: String,
+    description: String,
+}
+
+/// Renders the hover as Markdown, with the code fenced, for clients that
+/// can show it, and as just the description for those that can't
+fn hover_contents(hover: HoverText, markdown: bool) -> HoverContents {
+    if markdown {
+        HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```saffron\n{}\n```\n{}", hover.code, hover.description),
+        })
+    } else {
+        HoverContents::Scalar(MarkedString::String(hover.description))
+    }
+}
+
+/// The signature of the function being applied at the cursor, with the
+/// argument currently being typed as the active parameter
+fn signature_help(text: &str, tokens: &[Token], line: u32, character: u32) -> Option<SignatureHelp> {
+    let offset = line_col_to_offset(text, line, character);
+    let before: Vec<&Token> = tokens.iter().take_while(|t| t.end <= offset).collect();
+
+    // walk back to the start of the innermost unclosed application
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in before.iter().enumerate().rev() {
+        match token.content {
+            TokenContent::RParen => depth += 1,
+            TokenContent::LParen if depth > 0 => depth -= 1,
+            TokenContent::LParen | TokenContent::Equals | TokenContent::Newline if depth == 0 => {
+                start = i + 1;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let mut application = before[start..]
+        .iter()
+        .skip_while(|t| matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_)));
+    let TokenContent::Ident(name) = application.next()?.content else {
+        return None;
+    };
+    let site = parsing::top_level_definitions(tokens)
+        .into_iter()
+        .find(|site| site.name.content == TokenContent::Ident(name) && !site.params.is_empty())?;
+
+    let mut depth = 0;
+    let mut separators: u32 = 0;
+    let mut spacing = false;
+    for token in application {
+        let is_space = matches!(token.content, TokenContent::Space(_) | TokenContent::Tab(_));
+        match token.content {
+            TokenContent::LParen => depth += 1,
+            TokenContent::RParen => depth -= 1,
+            // spaces next to tabs are still one separator
+            _ if is_space && depth == 0 && !spacing => separators += 1,
+            _ => {}
+        }
+        spacing = is_space;
+    }
+
+    let mut label = name.to_string();
+    let mut parameters = vec![];
+    for param in &site.params {
+        label.push(' ');
+        // offsets into the label are in UTF-16 code units, like positions
+        let start = label.encode_utf16().count() as u32;
+        label.push_str(&text[param.start..param.end]);
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, label.encode_utf16().count() as u32]),
+            documentation: None,
+        });
+    }
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation: None,
+            parameters: Some(parameters),
+            active_parameter: None,
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(separators.saturating_sub(1)),
+    })
+}
+
+/// Byte spans growing outwards from the token at `offset`: the token, each
+/// bracketed group around it, the definition's body, the definition, and
+/// finally the whole document
+fn selection_spans(text: &str, tokens: &[Token], offset: usize) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    if let Some(token) = tokens.iter().find(|t| t.start <= offset && offset < t.end) {
+        spans.push((token.start, token.end));
+    }
+
+    let mut open = vec![];
+    let mut groups = vec![];
+    for token in tokens {
+        match token.content {
+            TokenContent::LParen | TokenContent::LBracket | TokenContent::LBrace => open.push(token.start),
+            TokenContent::RParen | TokenContent::RBracket | TokenContent::RBrace => {
+                if let Some(start) = open.pop() {
+                    groups.push((start, token.end));
+                }
+            }
+            _ => (),
+        }
+    }
+    groups.retain(|&(start, end)| start <= offset && offset < end);
+    groups.sort_by_key(|&(start, end)| end - start);
+    spans.extend(groups);
+
+    let sites = parsing::top_level_definitions(tokens);
+    if let Some(site) = sites.iter().find(|s| s.start <= offset && offset < s.end) {
+        let body = tokens
+            .iter()
+            .skip_while(|t| t.start < site.start || t.content != TokenContent::Equals)
+            .skip(1)
+            .find(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_)));
+        if let Some(body) = body.filter(|t| t.start <= offset) {
+            spans.push((body.start, site.end));
+        }
+        spans.push((site.start, site.end));
+    }
+    spans.push((0, text.len()));
+    spans.dedup();
+    spans
+}
+
+fn selection_range(text: &str, tokens: &[Token], position: Position) -> SelectionRange {
+    let offset = line_col_to_offset(text, position.line, position.character);
+    selection_spans(text, tokens, offset)
+        .into_iter()
+        .rev()
+        .fold(None, |parent, (start, end)| {
+            Some(SelectionRange {
+                range: Range::new(offset_to_position(text, start), offset_to_position(text, end)),
+                parent: parent.map(Box::new),
+            })
+        })
+        .unwrap_or(SelectionRange {
+            range: Range::new(position, position),
+            parent: None,
+        })
+}
+
+/// Folds for the body of a `module ... where` header and for every
+/// definition spanning more than one line
+fn folding_ranges(text: &str, tokens: &[Token]) -> Vec<FoldingRange> {
+    let fold = |start: usize, end: usize| {
+        let (start, end) = (offset_to_position(text, start).line, offset_to_position(text, end).line);
+        (start < end).then(|| FoldingRange {
+            start_line: start,
+            end_line: end,
+            kind: Some(FoldingRangeKind::Region),
+            ..FoldingRange::default()
+        })
+    };
+    let last = tokens
+        .iter()
+        .rev()
+        .find(|t| {
+            !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_) | TokenContent::Newline | TokenContent::Eof)
+        })
+        .map_or(0, |t| t.end);
+    let module = tokens
+        .iter()
+        .find(|t| t.content == TokenContent::Module)
+        .and_then(|header| fold(header.start, last));
+    module
+        .into_iter()
+        .chain(
+            parsing::top_level_definitions(tokens)
+                .into_iter()
+                .filter_map(|site| fold(site.start, site.end)),
+        )
+        .collect()
+}
+
+/// Re-emits the document with canonical spacing: single spaces between
+/// tokens and around `=`, no trailing whitespace, at most one blank line in a
+/// row, and exactly one blank line between consecutive definitions.
+/// Indentation keeps its width, with tabs expanded to `tab_width` spaces.
+fn format_document(text: &str, tokens: &[Token], tab_width: usize) -> String {
+    let newline = tokens
+        .iter()
+        .find(|t| t.content == TokenContent::Newline)
+        .map_or("\n", |t| &text[t.start..t.end]);
+    let definitions = parsing::top_level_definitions(tokens);
+    let owner = |offset: usize| definitions.iter().position(|site| site.start <= offset && offset <= site.end);
+
+    let mut lines: Vec<String> = vec![];
+    let mut previous_owner = None;
+    for line in tokens.split(|t| t.content == TokenContent::Newline) {
+        // indentation is significant, so only its tabs are changed
+        let mut formatted = " ".repeat(parsing::indentation(line, tab_width));
+        let mut separate = false;
+        let indented = line.iter().position(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_)));
+        for token in &line[indented.unwrap_or(line.len())..] {
+            let is_equals = token.content == TokenContent::Equals;
+            match token.content {
+                TokenContent::Space(_) | TokenContent::Tab(_) => separate = true,
+                _ => {
+                    if (separate || is_equals) && !formatted.trim_start().is_empty() {
+                        formatted.push(' ');
+                    }
+                    formatted.push_str(&text[token.start..token.end]);
+                    separate = is_equals;
+                }
+            }
+        }
+        let formatted = formatted.trim_end().to_string();
+        if formatted.is_empty() {
+            if lines.last().is_some_and(|l| !l.is_empty()) {
+                lines.push(formatted);
+            }
+            continue;
+        }
+        let current_owner = line.first().and_then(|t| owner(t.start));
+        let new_definition = current_owner.is_some() && previous_owner.is_some() && current_owner != previous_owner;
+        if new_definition && lines.last().is_some_and(|l| !l.is_empty()) {
+            lines.push(String::new());
+        }
+        previous_owner = current_owner;
+        lines.push(formatted);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.iter().map(|l| format!("{l}{newline}")).collect()
+}
+
+/// Quick fixes inserting a `name : _` signature above each definition in
+/// `range` that doesn't have one, indented to match the definition
+fn missing_signature_actions(text: &str, module: &saffron::Module, uri: &Url, range: Range) -> Vec<CodeActionOrCommand> {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    module
+        .members
+        .iter()
+        .filter(|definition| definition.def_type.is_none())
+        .filter_map(|definition| {
+            let start = offset_to_position(text, definition.name_span.start);
+            if start.line < range.start.line || start.line > range.end.line {
+                return None;
+            }
+            let line_start = text[..definition.name_span.start].rfind('\n').map_or(0, |i| i + 1);
+            let indentation = &text[line_start..definition.name_span.start];
+            let signature = format!("{}{} : _{}", indentation, definition.name, newline);
+            let edit = TextEdit::new(Range::new(Position::new(start.line, 0), Position::new(start.line, 0)), signature);
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Add a type signature for `{}`", definition.name),
+                kind: Some(CodeActionKind::QUICKFIX),
+                edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), vec![edit])]))),
+                ..CodeAction::default()
+            }))
+        })
+        .collect()
+}
+
+/// Quick fixes deleting each unused imported name in `range`, along with
+/// the comma separating it from its neighbours. An import left with nothing
+/// to import is removed entirely.
+fn unused_import_actions(text: &str, module: &saffron::Module, uri: &Url, range: Range) -> Vec<CodeActionOrCommand> {
+    let start = line_col_to_offset(text, range.start.line, range.start.character);
+    let end = line_col_to_offset(text, range.end.line, range.end.character);
+    let mut actions = vec![];
+    for unused in analysis::check_unused_imports(module) {
+        if unused.span.start > end || unused.span.end < start {
+            continue;
+        }
+        let Some(import) = module.imports.iter().find(|i| i.span.start <= unused.span.start && unused.span.end <= i.span.end)
+        else {
+            continue;
+        };
+        let names: Vec<&std::ops::Range<usize>> = match &import.reference {
+            ModuleReference::WildCard => continue,
+            ModuleReference::Single(name) => vec![&name.span],
+            ModuleReference::Many(names) => names.iter().map(|n| &n.span).collect(),
+        };
+        let Some(i) = names.iter().position(|span| **span == unused.span) else {
+            continue;
+        };
+        let removed = if names.len() == 1 {
+            let line_start = text[..import.span.start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = text[import.span.end..].find('\n').map_or(text.len(), |i| import.span.end + i + 1);
+            line_start..line_end
+        } else if i == 0 {
+            names[0].start..names[1].start
+        } else {
+            names[i - 1].end..names[i].end
+        };
+        let edit = TextEdit::new(
+            Range::new(offset_to_position(text, removed.start), offset_to_position(text, removed.end)),
+            String::new(),
+        );
+        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!("Remove unused import `{}`", &text[unused.span.clone()]),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), vec![edit])]))),
+            ..CodeAction::default()
+        }));
+    }
+    actions
+}
+
+/// A link on the module name of each import, to the file of that module in
+/// the package the document belongs to. Modules with no file there, and
+/// documents that aren't files, get links without a target.
+fn document_links(text: &str, module: &saffron::Module, uri: &Url) -> Vec<DocumentLink> {
+    let root = uri.to_file_path().ok().and_then(|file| saffron::package_root(&file, &module.name));
+    module
+        .imports
+        .iter()
+        .map(|import| {
+            let target = root
+                .as_ref()
+                .map(|root| saffron::module_path(root, &import.name.value))
+                .filter(|path| path.is_file())
+                .and_then(|path| Url::from_file_path(path).ok());
+            DocumentLink {
+                range: Range::new(
+                    offset_to_position(text, import.name.span.start),
+                    offset_to_position(text, import.name.span.end),
+                ),
+                target,
+                tooltip: None,
+                data: None,
+            }
+        })
+        .collect()
+}
+
+/// A `: Type` hint after the name of each definition in `range` that has no
+/// signature but whose type is fully inferred
+fn inlay_hints(text: &str, analysed: &Analysed, range: Range) -> Vec<InlayHint> {
+    analysed
+        .module
+        .members
+        .iter()
+        .zip(&analysed.types)
+        .filter(|(definition, ty)| definition.def_type.is_none() && ty.is_concrete())
+        .filter_map(|(definition, ty)| {
+            let position = offset_to_position(text, definition.name_span.end);
+            (range.start.line <= position.line && position.line <= range.end.line).then(|| InlayHint {
+                position,
+                label: InlayHintLabel::String(format!(": {}", ty)),
+                kind: Some(InlayHintKind::TYPE),
+                text_edits: None,
+                tooltip: None,
+                padding_left: Some(true),
+                padding_right: None,
+                data: None,
+            })
+        })
+        .collect()
+}
+
+/// Semantic token types, indexed by the `token_type` of each token sent
+const SEMANTIC_TOKEN_TYPES: [SemanticTokenType; 8] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::OPERATOR,
+];
+
+fn semantic_token_type(content: &TokenContent) -> Option<u32> {
+    let token_type = match content {
+        TokenContent::Module
+        | TokenContent::Where
+        | TokenContent::Import
+        | TokenContent::Let
+        | TokenContent::In
+        | TokenContent::If
+        | TokenContent::Then
+        | TokenContent::Else
+        | TokenContent::Case
+        | TokenContent::Of
+        | TokenContent::Infixl
+        | TokenContent::Infixr
+        | TokenContent::Infix => SemanticTokenType::KEYWORD,
+        TokenContent::Ident(_) => SemanticTokenType::VARIABLE,
+        TokenContent::Constructor(_) => SemanticTokenType::TYPE,
+        TokenContent::QualifiedName(_) => SemanticTokenType::NAMESPACE,
+        TokenContent::Char(_) | TokenContent::String(_) | TokenContent::MultilineString(_) => {
+            SemanticTokenType::STRING
+        }
+        TokenContent::Integer(_) | TokenContent::Float(_) => SemanticTokenType::NUMBER,
+        TokenContent::LineComment(_) | TokenContent::BlockComment(_) => SemanticTokenType::COMMENT,
+        TokenContent::Equals
+        | TokenContent::Arrow
+        | TokenContent::FatArrow
+        | TokenContent::Colon
+        | TokenContent::DoubleColon
+        | TokenContent::Backslash
+        | TokenContent::Operator(_) => SemanticTokenType::OPERATOR,
+        _ => return None,
+    };
+    SEMANTIC_TOKEN_TYPES.iter().position(|t| *t == token_type).map(|i| i as u32)
+}
+
+/// Delta-encodes the highlighted tokens of a document. Tokens spanning
+/// several lines are split into one token per line.
+fn semantic_tokens(text: &str, tokens: &[PositionedToken]) -> Vec<SemanticToken> {
+    let mut encoded = vec![];
+    let mut previous = Position::new(0, 0);
+    for positioned in tokens {
+        let token = &positioned.token;
+        let Some(token_type) = semantic_token_type(&token.content) else {
+            continue;
+        };
+        for (i, piece) in text[token.start..token.end].split('\n').enumerate() {
+            let position = if i == 0 {
+                positioned.start
+            } else {
+                Position::new(positioned.start.line + i as u32, 0)
+            };
+            let piece = piece.strip_suffix('\r').unwrap_or(piece);
+            if !piece.is_empty() {
+                let delta_line = position.line - previous.line;
+                encoded.push(SemanticToken {
+                    delta_line,
+                    delta_start: if delta_line == 0 {
+                        position.character - previous.character
+                    } else {
+                        position.character
+                    },
+                    length: piece.encode_utf16().count() as u32,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                });
+                previous = position;
+            }
+        }
+    }
+    encoded
+}
+
+type Lexed<'doc> = (Vec<Token<'doc>>, Vec<ParseError>);
+
+self_cell!(
+    /// The tokens of one version of a document and the errors hit lexing it,
+    /// kept alongside a copy of the text they borrow from
+    struct LexedText {
+        owner: String,
+        #[covariant]
+        dependent: Lexed,
+    }
+);
+
+/// A document parsed into a module, with the inferred type of each of its
+/// definitions in the same order
+struct Analysed {
+    module: saffron::Module,
+    types: Vec<types::Type>,
+}
+
+impl Analysed {
+    fn new(text: &str, tab_width: usize) -> Analysed {
+        let (module, _) = saffron::Module::parse_with_tab_width(text, tab_width);
+        let (types, _) = types::infer_module(&module);
+        Analysed { module, types }
+    }
+
+    /// The inferred type of the top-level definition `name`
+    fn type_of(&self, name: &str) -> Option<&types::Type> {
+        let (_, ty) = self.module.members.iter().zip(&self.types).find(|(definition, _)| definition.name == name)?;
+        Some(ty)
+    }
+}
+
+/// One version of a document as requests see it. It's lexed as soon as
+/// it's made, and parsed and type checked the first time a request needs
+/// the module.
+struct Snapshot {
+    lexed: LexedText,
+    tab_width: usize,
+    analysed: OnceLock<Analysed>,
+}
+
+impl Snapshot {
+    fn new(text: String, tab_width: usize) -> Snapshot {
+        let lexed = LexedText::new(text, |text| parsing::lex_line_resilient(text));
+        Snapshot { lexed, tab_width, analysed: OnceLock::new() }
+    }
+
+    fn text(&self) -> &str {
+        self.lexed.borrow_owner()
+    }
+
+    fn tokens(&self) -> &[Token<'_>] {
+        &self.lexed.borrow_dependent().0
+    }
+
+    /// The first character of the document that didn't lex, if any
+    fn lex_error(&self) -> Option<&ParseError> {
+        self.lexed.borrow_dependent().1.first()
+    }
+
+    fn analysed(&self) -> &Analysed {
+        self.analysed.get_or_init(|| Analysed::new(self.text(), self.tab_width))
+    }
+}
+
+/// An open document. A snapshot of it is made the first time a request
+/// needs one, and reused until the document changes. Its symbols are read
+/// as soon as it opens or changes.
+struct Document {
+    version: i32,
     text: String,
+    snapshot: Option<(i32, Arc<Snapshot>)>,
+    symbols: Arc<Module>,
+}
+
+impl Document {
+    fn new(version: i32, text: String, settings: &Settings) -> Document {
+        let mut document = Document { version, text, snapshot: None, symbols: Arc::default() };
+        document.read_symbols(settings);
+        document
+    }
+
+    /// Fills the symbol store from the current text, leaving it empty for
+    /// documents too big to analyse
+    fn read_symbols(&mut self, settings: &Settings) {
+        self.symbols = match settings.oversized(&self.text) {
+            Some(_) => Arc::default(),
+            None => Arc::new(Module::new(&self.text)),
+        };
+    }
+}
+
+/// What the client asked for when initializing, through its options and
+/// capabilities
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct Settings {
+    /// `tabWidth`
+    tab_width: usize,
+    /// `maxDocumentSize`, in bytes
+    max_document_size: usize,
+    /// `maxLineLength`, in bytes
+    max_line_length: usize,
+    /// Whether the client can render hovers written in Markdown
+    markdown_hover: bool,
+    /// `diagnostics`, how each check is reported, in the order of
+    /// `Check::ALL`
+    reporting: [Reporting; Check::ALL.len()],
+}
+
+/// The initialization options read into `Settings`
+const OPTIONS: [&str; 4] = ["tabWidth", "maxDocumentSize", "maxLineLength", "diagnostics"];
+
+/// The key of each check within the `diagnostics` option
+fn check_option(check: Check) -> &'static str {
+    match check {
+        Check::Duplicates => "duplicateDefinition",
+        Check::Undefined => "undefinedName",
+        Check::UnusedImports => "unusedImport",
+        Check::Types => "typeError",
+    }
+}
+
+/// How the diagnostics of one check are published
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Reporting {
+    /// At the severity the check gives them
+    AsChecked,
+    /// All at this severity
+    At(DiagnosticSeverity),
+    /// Not at all
+    Off,
+}
+
+impl Reporting {
+    /// The reporting named by a value of the `diagnostics` option, which is
+    /// a severity or `off`
+    fn parse(name: &str) -> Option<Reporting> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Reporting::At(DiagnosticSeverity::ERROR)),
+            "warning" => Some(Reporting::At(DiagnosticSeverity::WARNING)),
+            "information" => Some(Reporting::At(DiagnosticSeverity::INFORMATION)),
+            "hint" => Some(Reporting::At(DiagnosticSeverity::HINT)),
+            "off" => Some(Reporting::Off),
+            _ => None,
+        }
+    }
+
+    /// `diagnostic` as it's published, if it is
+    fn apply(self, mut diagnostic: saffron::Diagnostic) -> Option<saffron::Diagnostic> {
+        match self {
+            Reporting::AsChecked => Some(diagnostic),
+            Reporting::At(severity) => {
+                diagnostic.severity = severity;
+                Some(diagnostic)
+            }
+            Reporting::Off => None,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            tab_width: parsing::DEFAULT_TAB_WIDTH,
+            max_document_size: usize::MAX,
+            max_line_length: usize::MAX,
+            markdown_hover: false,
+            reporting: [Reporting::AsChecked; Check::ALL.len()],
+        }
+    }
+}
+
+impl Settings {
+    /// The settings from `params`, with defaults for anything missing or
+    /// not a positive number. A check missing from `diagnostics`, or given
+    /// something other than a severity or `off`, is reported as it's found.
+    fn from_params(params: &InitializeParams) -> Settings {
+        let option = |key: &str| {
+            let value = params.initialization_options.as_ref()?.get(key)?.as_u64()?;
+            (value > 0).then_some(value as usize)
+        };
+        let reporting = |check: Check| {
+            let options = params.initialization_options.as_ref()?.get("diagnostics")?;
+            Reporting::parse(options.get(check_option(check))?.as_str()?)
+        };
+        let default = Settings::default();
+        Settings {
+            tab_width: option("tabWidth").unwrap_or(default.tab_width),
+            max_document_size: option("maxDocumentSize").unwrap_or(default.max_document_size),
+            max_line_length: option("maxLineLength").unwrap_or(default.max_line_length),
+            reporting: Check::ALL.map(|check| reporting(check).unwrap_or(Reporting::AsChecked)),
+            markdown_hover: params
+                .capabilities
+                .text_document
+                .as_ref()
+                .and_then(|document| document.hover.as_ref()?.content_format.as_ref())
+                .is_some_and(|formats| formats.contains(&MarkupKind::Markdown)),
+        }
+    }
+
+    /// The initialization options in `params` that aren't settings, which
+    /// are ignored. Keys within `diagnostics` are given as `diagnostics.key`.
+    fn ignored_options(params: &InitializeParams) -> Vec<String> {
+        let Some(options) = params.initialization_options.as_ref().and_then(|o| o.as_object()) else {
+            return vec![];
+        };
+        let mut ignored: Vec<String> = options.keys().filter(|key| !OPTIONS.contains(&key.as_str())).cloned().collect();
+        if let Some(checks) = options.get("diagnostics").and_then(|d| d.as_object()) {
+            let known = Check::ALL.map(check_option);
+            ignored.extend(
+                checks
+                    .keys()
+                    .filter(|key| !known.contains(&key.as_str()))
+                    .map(|key| format!("diagnostics.{}", key)),
+            );
+        }
+        ignored
+    }
+
+    /// How the diagnostics of `check` are published
+    fn reporting(&self, check: Check) -> Reporting {
+        self.reporting[check as usize]
+    }
+
+    /// Why `text` is too big to analyse, if it is
+    fn oversized(&self, text: &str) -> Option<String> {
+        if text.len() > self.max_document_size {
+            return Some(format!("documents over {} bytes aren't analysed", self.max_document_size));
+        }
+        text.lines()
+            .any(|line| line.len() > self.max_line_length)
+            .then(|| format!("documents with lines over {} bytes aren't analysed", self.max_line_length))
+    }
+}
+
+/// Diagnostics from every analysis pass, which are too slow to run on each
+/// change so wait for the document to be saved. Each check is reported as
+/// the settings ask.
+fn save_diagnostics(text: &str, uri: &Url, settings: &Settings) -> Vec<Diagnostic> {
+    if settings.oversized(text).is_some() {
+        return change_diagnostics(text, uri, settings);
+    }
+    let (module, mut found) = saffron::Module::parse_with_tab_width(text, settings.tab_width);
+    for check in Check::ALL {
+        found.extend(check.run(&module).into_iter().filter_map(|d| settings.reporting(check).apply(d)));
+    }
+    found.sort_by_key(|d| d.span.start);
+    found.iter().map(|d| lsp_diagnostic(text, uri, d)).collect()
+}
+
+/// The diagnostics published as a document changes. Documents too big to
+/// analyse get a single note saying so instead.
+fn change_diagnostics(text: &str, uri: &Url, settings: &Settings) -> Vec<Diagnostic> {
+    match settings.oversized(text) {
+        Some(reason) => vec![Diagnostic {
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            source: Some("saffron".to_string()),
+            message: reason,
+            ..Diagnostic::default()
+        }],
+        None => lex_diagnostics(text, uri),
+    }
 }
 
 struct Backend {
     client: Client,
-    text_file: Arc<Mutex<String>>,
+    documents: Arc<Mutex<HashMap<Url, Document>>>,
+    settings: Mutex<Settings>,
+}
+
+impl Backend {
+    fn new(client: Client) -> Backend {
+        Backend {
+            client,
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            settings: Mutex::new(Settings::default()),
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.lock().map(|settings| *settings).unwrap_or_default()
+    }
+
+    /// Runs `f` on the text of an open document, unless it's too big to
+    /// analyse
+    fn with_document<T>(&self, uri: &Url, f: impl FnOnce(&str) -> T) -> Option<T> {
+        let settings = self.settings();
+        let documents = self.documents.lock().ok()?;
+        let document = documents.get(uri)?;
+        settings.oversized(&document.text).is_none().then(|| f(&document.text))
+    }
+
+    /// A snapshot of the current version of a document, made only if it
+    /// has changed since one was last asked for. The text, tokens and module
+    /// in it all come from the same version, so a request racing a change
+    /// still sees a consistent document. Characters that don't lex become
+    /// `Error` tokens rather than losing the rest of the document. Documents
+    /// too big to analyse are never lexed.
+    fn snapshot(&self, uri: &Url) -> Option<Arc<Snapshot>> {
+        let settings = self.settings();
+        let mut documents = self.documents.lock().ok()?;
+        let document = documents.get_mut(uri)?;
+        if settings.oversized(&document.text).is_some() {
+            return None;
+        }
+        match &document.snapshot {
+            Some((version, snapshot)) if *version == document.version => Some(snapshot.clone()),
+            _ => {
+                let snapshot = Arc::new(Snapshot::new(document.text.clone(), settings.tab_width));
+                document.snapshot = Some((document.version, snapshot.clone()));
+                Some(snapshot)
+            }
+        }
+    }
+
+    /// The symbol store of an open document
+    fn symbols(&self, uri: &Url) -> Option<Arc<Module>> {
+        let documents = self.documents.lock().ok()?;
+        documents.get(uri).map(|document| document.symbols.clone())
+    }
+
+    /// Runs `f` on an open document and its tokens
+    fn with_tokens<T>(&self, uri: &Url, f: impl FnOnce(&str, &[Token]) -> T) -> Option<T> {
+        let snapshot = self.snapshot(uri)?;
+        Some(f(snapshot.text(), snapshot.tokens()))
+    }
+
+    /// Runs `f` on a snapshot of an open document
+    fn with_snapshot<T>(&self, uri: &Url, f: impl FnOnce(&Snapshot) -> T) -> Option<T> {
+        self.snapshot(uri).map(|snapshot| f(&snapshot))
+    }
+
+    async fn publish_lex_diagnostics(&self, uri: Url, version: Option<i32>) {
+        let settings = self.settings();
+        let diagnostics = match self.documents.lock() {
+            Ok(documents) => documents.get(&uri).map(|document| change_diagnostics(&document.text, &uri, &settings)),
+            Err(_) => None,
+        };
+        if let Some(diagnostics) = diagnostics {
+            self.client.publish_diagnostics(uri, diagnostics, version).await;
+        }
+    }
+
+    /// The full diagnostics of the latest version of a document, with that
+    /// version
+    fn saved_diagnostics(&self, uri: &Url) -> Option<(Vec<Diagnostic>, i32)> {
+        let settings = self.settings();
+        let documents = self.documents.lock().ok()?;
+        let document = documents.get(uri)?;
+        Some((save_diagnostics(&document.text, uri, &settings), document.version))
+    }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, x: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         self.client
             .log_message(MessageType::INFO, "initalizing...")
             .await;
+        if let Ok(mut settings) = self.settings.lock() {
+            *settings = Settings::from_params(&params);
+        }
+        for option in Settings::ignored_options(&params) {
+            self.client
+                .log_message(MessageType::INFO, format!("ignoring unknown option `{}`", option))
+                .await;
+        }
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
-                )),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::INCREMENTAL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..TextDocumentSyncOptions::default()
+                })),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
-                completion_provider: Some(CompletionOptions {
+                document_symbol_provider: Some(OneOf::Left(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), " ".to_string()]),
+                    ..SignatureHelpOptions::default()
+                }),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
                     resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+                    SemanticTokensOptions {
+                        legend: SemanticTokensLegend {
+                            token_types: SEMANTIC_TOKEN_TYPES.to_vec(),
+                            token_modifiers: vec![],
+                        },
+                        full: Some(SemanticTokensFullOptions::Bool(true)),
+                        ..SemanticTokensOptions::default()
+                    },
+                )),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(true),
                     trigger_characters: Some(vec![".".to_string()]),
                     ..CompletionOptions::default()
                 }),
@@ -53,13 +1330,15 @@ impl LanguageServer for Backend {
                 format!("did open '{}'", params.text_document.uri.as_str()),
             )
             .await;
-        let text = params.text_document.text;
-        if let Ok(mut text_file) = self.text_file.lock() {
-            *text_file = text;
+        let doc = params.text_document;
+        let settings = self.settings();
+        if let Ok(mut documents) = self.documents.lock() {
+            documents.insert(doc.uri.clone(), Document::new(doc.version, doc.text, &settings));
         }
         self.client
             .log_message(MessageType::INFO, "loaded text".to_string())
             .await;
+        self.publish_lex_diagnostics(doc.uri, Some(doc.version)).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
@@ -69,25 +1348,56 @@ impl LanguageServer for Backend {
                 format!("did change'{}'", params.text_document.uri.as_str()),
             )
             .await;
+        let uri = params.text_document.uri;
         for change in params.content_changes {
-            if let (Some(range), Some(range_length)) = { (change.range, change.range_length) } {
-                self.client
-                    .log_message(MessageType::INFO, format!("change '{}'", change.text))
-                    .await;
+            self.client
+                .log_message(MessageType::INFO, format!("change '{}'", change.text))
+                .await;
 
-                if let Ok(mut text_file) = self.text_file.lock() {
-                    *text_file = change.text;
+            if let Ok(mut documents) = self.documents.lock() {
+                if let Some(document) = documents.get_mut(&uri) {
+                    apply_change(&mut document.text, &change);
+                    document.version = params.text_document.version;
+                    document.snapshot = None;
                 }
             }
         }
+        let settings = self.settings();
+        if let Ok(mut documents) = self.documents.lock() {
+            if let Some(document) = documents.get_mut(&uri) {
+                document.read_symbols(&settings);
+            }
+        }
+        self.publish_lex_diagnostics(uri, Some(params.text_document.version)).await;
     }
 
-    async fn initialized(&self, _: InitializedParams) {
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
         self.client
-            .show_message(MessageType::INFO, "Saffron lsp started!")
+            .log_message(MessageType::INFO, format!("did save '{}'", uri.as_str()))
             .await;
-        self.client
-            .log_message(MessageType::INFO, "server initialized!")
+        if let Some((diagnostics, version)) = self.saved_diagnostics(&uri) {
+            self.client.publish_diagnostics(uri, diagnostics, Some(version)).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.client
+            .log_message(MessageType::INFO, format!("did close '{}'", uri.as_str()))
+            .await;
+        if let Ok(mut documents) = self.documents.lock() {
+            documents.remove(&uri);
+        }
+        self.client.publish_diagnostics(uri, vec![], None).await;
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client
+            .show_message(MessageType::INFO, "Saffron lsp started!")
+            .await;
+        self.client
+            .log_message(MessageType::INFO, "server initialized!")
             .await;
     }
 
@@ -95,27 +1405,198 @@ impl LanguageServer for Backend {
         Ok(())
     }
 
-    async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         self.client
             .log_message(MessageType::INFO, "completition triggered")
             .await;
-        Ok(Some(CompletionResponse::Array(vec![
-            CompletionItem::new_simple("Hello".to_string(), "Some detail".to_string()),
-            CompletionItem::new_simple("Bye".to_string(), "More detail".to_string()),
-        ])))
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let symbols = self.symbols(&uri).unwrap_or_default();
+        // the document is remembered on each item so resolving it can
+        // find the definition again
+        let data = Some(uri.to_string().into());
+        Ok(self
+            .with_tokens(&uri, |text, tokens| completions(text, tokens, &symbols, pos.line, pos.character))
+            .map(|items| items.into_iter().map(|item| CompletionItem { data: data.clone(), ..item }).collect())
+            .map(CompletionResponse::Array))
     }
 
-    async fn completion_resolve(&self, _: CompletionItem) -> Result<CompletionItem> {
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
         self.client
             .log_message(MessageType::INFO, "completion resolve")
             .await;
-        Ok(CompletionItem {
-            label: "Item!".to_string(),
-            ..Default::default()
-        })
+        let uri = item.data.as_ref().and_then(|data| Url::parse(data.as_str()?).ok());
+        let Some(uri) = uri else {
+            return Ok(item);
+        };
+        let symbols = self.symbols(&uri).unwrap_or_default();
+        let resolved = self.with_snapshot(&uri, |snapshot| {
+            resolve_completion(snapshot.tokens(), snapshot.analysed(), &symbols, item.clone())
+        });
+        Ok(resolved.unwrap_or(item))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        Ok(self
+            .with_tokens(&params.text_document.uri, |text, tokens| {
+                DocumentSymbolResponse::Nested(document_symbols(text, tokens))
+            }))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let pos = params.text_document_position_params.position;
+        Ok(self
+            .with_snapshot(&uri, |snapshot| {
+                let module = &snapshot.analysed().module;
+                definition_range(snapshot.text(), snapshot.tokens(), module, pos.line, pos.character)
+            })
+            .flatten()
+            .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri, range))))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let include_declaration = params.context.include_declaration;
+        Ok(self
+            .with_snapshot(&uri, |snapshot| {
+                let (text, module) = (snapshot.text(), &snapshot.analysed().module);
+                references(text, snapshot.tokens(), module, pos.line, pos.character, include_declaration)
+                    .into_iter()
+                    .map(|t| Location::new(uri.clone(), t.lsp_range(text)))
+                    .collect()
+            }))
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let pos = params.text_document_position_params.position;
+        Ok(self
+            .with_tokens(&params.text_document_position_params.text_document.uri, |text, tokens| {
+                signature_help(text, tokens, pos.line, pos.character)
+            })
+            .flatten())
+    }
+
+    async fn document_highlight(&self, params: DocumentHighlightParams) -> Result<Option<Vec<DocumentHighlight>>> {
+        let pos = params.text_document_position_params.position;
+        Ok(self
+            .with_snapshot(&params.text_document_position_params.text_document.uri, |snapshot| {
+                let module = &snapshot.analysed().module;
+                document_highlights(snapshot.text(), snapshot.tokens(), module, pos.line, pos.character)
+            }))
+    }
+
+    async fn prepare_rename(&self, params: TextDocumentPositionParams) -> Result<Option<PrepareRenameResponse>> {
+        let pos = params.position;
+        // a name inside a string that doesn't close lexes as an identifier,
+        // so nothing is renamed until the document lexes
+        let range = self.with_snapshot(&params.text_document.uri, |snapshot| match snapshot.lex_error() {
+            None => {
+                let module = &snapshot.analysed().module;
+                prepare_rename_range(snapshot.text(), snapshot.tokens(), module, pos.line, pos.character)
+            }
+            Some(e) => Err(Error::invalid_params(e.to_string())),
+        });
+        match range {
+            Some(range) => Ok(Some(PrepareRenameResponse::Range(range?))),
+            None => Ok(None),
+        }
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let edits = self.with_snapshot(&uri, |snapshot| match snapshot.lex_error() {
+            None => {
+                let module = &snapshot.analysed().module;
+                rename_edits(snapshot.text(), snapshot.tokens(), module, pos.line, pos.character, &params.new_name)
+            }
+            Some(e) => Err(Error::invalid_params(e.to_string())),
+        });
+        match edits {
+            Some(edits) => Ok(Some(WorkspaceEdit::new(HashMap::from([(uri, edits?)])))),
+            None => Ok(None),
+        }
+    }
+
+    async fn selection_range(&self, params: SelectionRangeParams) -> Result<Option<Vec<SelectionRange>>> {
+        Ok(self
+            .with_tokens(&params.text_document.uri, |text, tokens| {
+                params
+                    .positions
+                    .iter()
+                    .map(|&pos| selection_range(text, tokens, pos))
+                    .collect()
+            }))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        Ok(self
+            .with_tokens(&params.text_document.uri, folding_ranges))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let tab_width = self.settings().tab_width;
+        // laying out `Error` tokens could move text that only looks like
+        // code, so a document that doesn't lex is left alone
+        let Some(snapshot) = self.snapshot(&params.text_document.uri).filter(|s| s.lex_error().is_none()) else {
+            return Ok(None);
+        };
+        let text = snapshot.text();
+        let formatted = format_document(text, snapshot.tokens(), tab_width);
+        Ok(Some(if formatted == text {
+            vec![]
+        } else {
+            vec![TextEdit::new(
+                Range::new(Position::new(0, 0), offset_to_position(text, text.len())),
+                formatted,
+            )]
+        }))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        Ok(self.with_snapshot(&uri, |snapshot| {
+            let (text, module) = (snapshot.text(), &snapshot.analysed().module);
+            let mut actions = missing_signature_actions(text, module, &uri, params.range);
+            actions.extend(unused_import_actions(text, module, &uri, params.range));
+            actions
+        }))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        Ok(self.with_snapshot(&params.text_document.uri, |snapshot| {
+            inlay_hints(snapshot.text(), snapshot.analysed(), params.range)
+        }))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        Ok(self.with_snapshot(&uri, |snapshot| document_links(snapshot.text(), &snapshot.analysed().module, &uri)))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        Ok(self
+            .with_document(&params.text_document.uri, |text| {
+                SemanticTokensResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data: semantic_tokens(text, &tokenize_document(text)),
+                })
+            }))
     }
 
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
         let pos = params.text_document_position_params.position;
         self.client
             .log_message(
@@ -123,47 +1604,1149 @@ impl LanguageServer for Backend {
                 format!("hover at '{}' '{}'", pos.line, pos.character),
             )
             .await;
-        let mut msg = "".to_string();
-        if let Ok(text_file) = self.text_file.lock() {
-            let str = text_file.as_str();
-            if let Ok(tokens) = parsing::lex_line(str) {
-                msg = format!("{:?}", tokens);
-                if let Some(result) = tokens
-                    .iter()
-                    .filter(|t| t.position.location_offset() >= pos.character.try_into().unwrap())
-                    .last()
-                {
-                    return Ok(Some(Hover {
-                        contents: HoverContents::Scalar(MarkedString::String(
-                            format!("You're hovering on a {:?}", result.content).to_string(),
-                        )),
-                        range: None,
-                    }));
-                }
-            }
+        let symbols = self.symbols(&uri).unwrap_or_default();
+        let found = self.with_snapshot(&uri, |snapshot| {
+            hover_message(snapshot.text(), snapshot.tokens(), snapshot.analysed(), &symbols, pos.line, pos.character)
+        });
+        match found {
+            None => Ok(None),
+            Some(Some((message, range))) => Ok(Some(Hover {
+                contents: hover_contents(message, self.settings().markdown_hover),
+                range: Some(range),
+            })),
+            Some(None) => Ok(Some(Hover {
+                contents: HoverContents::Scalar(MarkedString::String(
+                    "Not sure what this is".to_string(),
+                )),
+                range: None,
+            })),
         }
-        self.client
-            .log_message(MessageType::INFO, format!("tokens were '{}'", msg))
-            .await;
+    }
+}
 
-        return Ok(Some(Hover {
-            contents: HoverContents::Scalar(MarkedString::String(
-                "Not sure what this is".to_string(),
-            )),
-            range: None,
-        }));
+/// Where a byte offset is, as the one-based `line:column` editors show
+fn location(text: &str, offset: usize) -> String {
+    let position = offset_to_position(text, offset);
+    format!("{}:{}", position.line + 1, position.character + 1)
+}
+
+/// Each token of `text` on a line of its own, leaving out whitespace
+fn lex_command(text: &str) -> std::result::Result<String, String> {
+    let tokens = parsing::lex_line(text)
+        .map_err(|e| format!("{}: {}\n", location(text, e.offset().unwrap_or(0)), e))?;
+    Ok(tokens
+        .iter()
+        .filter(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_) | TokenContent::Newline))
+        .map(|t| format!("{} {:?}\n", location(text, t.start), t.content))
+        .collect())
+}
+
+/// The parsed module, or every problem with it when there are errors
+fn parse_command(text: &str) -> std::result::Result<String, String> {
+    let (module, diagnostics) = saffron::Module::parse(text);
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.severity == DiagnosticSeverity::ERROR)
+        .map(|d| format!("{}: {}\n", location(text, d.span.start), d.message))
+        .collect();
+    if errors.is_empty() {
+        Ok(format!("{:#?}\n", module))
+    } else {
+        Err(errors.concat())
     }
 }
 
 #[tokio::main]
 async fn main() {
+    // `lex <file>` and `parse <file>` print what the server would see;
+    // anything else runs the server
+    let args: Vec<String> = std::env::args().collect();
+    if let [program, command, path] = &args[..] {
+        let run = match command.as_str() {
+            "lex" => lex_command,
+            "parse" => parse_command,
+            _ => {
+                eprintln!("usage: {} [lex|parse] <file>", program);
+                std::process::exit(2)
+            }
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1)
+            }
+        };
+        match run(&text) {
+            Ok(output) => print!("{}", output),
+            Err(errors) => {
+                eprint!("{}", errors);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let text_file = Arc::new(Mutex::new("".to_string()));
-    let (service, socket) = LspService::new(|client| Backend {
-        client: client,
-        text_file: text_file,
-    });
+    let (service, socket) = LspService::new(Backend::new);
     Server::new(stdin, stdout, socket).serve(service).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saffron::lex_line;
+
+    fn test_uri() -> Url {
+        Url::parse("file:///a.saffron").unwrap()
+    }
+
+    /// A backend to call the handlers of directly. Its client is never
+    /// initialised, so whatever it would send is dropped.
+    fn test_backend() -> Backend {
+        let mut client = None;
+        let _ = LspService::new(|c: Client| {
+            client = Some(c.clone());
+            Backend::new(c)
+        });
+        Backend::new(client.unwrap())
+    }
+
+    fn open_params(uri: &Url, text: &str) -> DidOpenTextDocumentParams {
+        DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "saffron".to_string(), 0, text.to_string()),
+        }
+    }
+
+    fn ranged_change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn change_params(uri: &Url, changes: Vec<TextDocumentContentChangeEvent>) -> DidChangeTextDocumentParams {
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+            content_changes: changes,
+        }
+    }
+
+    fn hover_params(uri: &Url, line: u32, character: u32) -> HoverParams {
+        HoverParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(line, character),
+            ),
+            work_done_progress_params: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn documents_are_stored_per_uri() {
+        let backend = test_backend();
+        let a = test_uri();
+        let b = Url::parse("file:///b.saffron").unwrap();
+
+        backend.did_open(open_params(&a, "a = 1")).await;
+        backend.did_open(open_params(&b, "b = 2")).await;
+        backend
+            .did_change(change_params(&b, vec![ranged_change((0, 4), (0, 5), "3")]))
+            .await;
+
+        let documents = backend.documents.lock().unwrap();
+        assert_eq!(documents.get(&a).map(|d| d.text.as_str()), Some("a = 1"));
+        assert_eq!(documents.get(&b).map(|d| d.text.as_str()), Some("b = 3"));
+    }
+
+    #[test]
+    fn can_apply_edits() {
+        let mut text = "foo = 1\nbar = 2".to_string();
+        let edit = |text: &mut String, start: (u32, u32), end: (u32, u32), new_text: &str| {
+            apply_edit(text, Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)), new_text)
+        };
+        // replace
+        edit(&mut text, (0, 6), (0, 7), "42");
+        assert_eq!(text, "foo = 42\nbar = 2");
+        // insert
+        edit(&mut text, (1, 0), (1, 0), "  ");
+        assert_eq!(text, "foo = 42\n  bar = 2");
+        // delete across lines
+        edit(&mut text, (0, 8), (1, 2), " ");
+        assert_eq!(text, "foo = 42 bar = 2");
+        // append past the end
+        edit(&mut text, (0, 16), (0, 16), "\nbaz = 3");
+        assert_eq!(text, "foo = 42 bar = 2\nbaz = 3");
+    }
+
+    #[test]
+    fn change_without_range_replaces_document() {
+        let mut text = "foo = 1\nbar = 2".to_string();
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "baz = 3".to_string(),
+        };
+        apply_change(&mut text, &change);
+        assert_eq!(text, "baz = 3");
+    }
+
+    #[test]
+    fn change_with_range_splices() {
+        let mut text = "foo = 1\nbar = 2".to_string();
+        apply_change(&mut text, &ranged_change((1, 0), (1, 3), "baz"));
+        assert_eq!(text, "foo = 1\nbaz = 2");
+    }
+
+    #[tokio::test]
+    async fn did_change_applies_incremental_edits_in_order() {
+        let backend = test_backend();
+        let uri = test_uri();
+
+        backend.did_open(open_params(&uri, "x = 1")).await;
+        backend
+            .did_change(change_params(
+                &uri,
+                vec![
+                    ranged_change((0, 0), (0, 1), "answer"),
+                    ranged_change((0, 9), (0, 10), "42"),
+                    ranged_change((0, 11), (0, 11), "\ny = 2"),
+                ],
+            ))
+            .await;
+
+        let documents = backend.documents.lock().unwrap();
+        assert_eq!(documents.get(&uri).map(|d| d.text.as_str()), Some("answer = 42\ny = 2"));
+    }
+
+    #[tokio::test]
+    async fn did_close_forgets_document() {
+        let backend = test_backend();
+        let uri = test_uri();
+
+        backend.did_open(open_params(&uri, "x = 1")).await;
+        backend
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+            })
+            .await;
+
+        assert!(backend.documents.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hover_on_unopened_document_is_none() {
+        let backend = test_backend();
+        let uri = Url::parse("file:///missing.saffron").unwrap();
+        assert_eq!(backend.hover(hover_params(&uri, 0, 0)).await.unwrap(), None);
+    }
+
+    #[test]
+    fn lex_error_becomes_diagnostic() {
+        let diagnostics = lex_diagnostics("foo = 1\nbar @ baz", &test_uri());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(1, 4), Position::new(1, 5)));
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].message, "unexpected character `@`");
+    }
+
+    #[test]
+    fn every_lex_error_becomes_a_diagnostic() {
+        let diagnostics = lex_diagnostics("foo = 1\nbar @ baz @\nqux = 2 ?\n", &test_uri());
+        let ranges: Vec<_> = diagnostics.iter().map(|d| d.range).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Range::new(Position::new(1, 4), Position::new(1, 5)),
+                Range::new(Position::new(1, 10), Position::new(1, 11)),
+                Range::new(Position::new(2, 8), Position::new(2, 9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn saving_runs_every_analysis() {
+        let text = "module Main where\nimport Data.List (map)\nmain = missing 42\nmain = 1 2\n";
+        let settings = Settings::default();
+        assert_eq!(change_diagnostics(text, &test_uri(), &settings), vec![]);
+        let messages: Vec<_> = save_diagnostics(text, &test_uri(), &settings).into_iter().map(|d| d.message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "`map` is imported but never used",
+                "`main` has no type signature",
+                "`missing` isn't defined",
+                "`main` has no type signature",
+                "`main` is already defined",
+                "`Int` isn't a function, so it can't be applied",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn saving_analyses_the_latest_text() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "main : Int\nmain = 42\n")).await;
+        assert_eq!(backend.saved_diagnostics(&uri), Some((vec![], 0)));
+
+        backend
+            .did_change(change_params(&uri, vec![ranged_change((1, 7), (1, 9), "answer")]))
+            .await;
+        let (diagnostics, version) = backend.saved_diagnostics(&uri).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`answer` isn't defined");
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(1, 7), Position::new(1, 13)));
+    }
+
+    #[tokio::test]
+    async fn saved_duplicates_point_at_the_first_definition() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "main : Int\nmain = 1\nmain = 2\n")).await;
+        backend
+            .did_save(DidSaveTextDocumentParams { text_document: TextDocumentIdentifier::new(uri.clone()), text: None })
+            .await;
+        let (diagnostics, _) = backend.saved_diagnostics(&uri).unwrap();
+        let duplicate = diagnostics.iter().find(|d| d.message == "`main` is already defined").unwrap();
+        assert_eq!(
+            duplicate.related_information,
+            Some(vec![DiagnosticRelatedInformation {
+                location: Location::new(uri, Range::new(Position::new(1, 0), Position::new(1, 4))),
+                message: "`main` is first defined here".to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn clean_document_has_no_diagnostics() {
+        assert_eq!(lex_diagnostics("foo = 1\nbar = baz", &test_uri()), vec![]);
+    }
+
+    #[test]
+    fn definitions_become_document_symbols() {
+        let text = "answer = 42\nadd x y =\n  x + y\n";
+        let tokens = lex_line(text).unwrap();
+        let symbols = document_symbols(text, &tokens);
+        assert_eq!(symbols.len(), 2);
+
+        assert_eq!(symbols[0].name, "answer");
+        assert_eq!(symbols[0].kind, SymbolKind::CONSTANT);
+        assert_eq!(symbols[0].range, Range::new(Position::new(0, 0), Position::new(0, 11)));
+        assert_eq!(symbols[0].selection_range, Range::new(Position::new(0, 0), Position::new(0, 6)));
+
+        assert_eq!(symbols[1].name, "add");
+        assert_eq!(symbols[1].kind, SymbolKind::FUNCTION);
+        assert_eq!(symbols[1].range, Range::new(Position::new(1, 0), Position::new(2, 7)));
+        assert_eq!(symbols[1].selection_range, Range::new(Position::new(1, 0), Position::new(1, 3)));
+    }
+
+    #[test]
+    fn can_find_definition_of_reference() {
+        let text = "answer = 42\nmain = answer\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let definition = Some(Range::new(Position::new(0, 0), Position::new(0, 6)));
+        assert_eq!(definition_range(text, &tokens, &module, 1, 9), definition);
+        // the definition's own name goes to itself
+        assert_eq!(definition_range(text, &tokens, &module, 0, 2), definition);
+    }
+
+    #[test]
+    fn definition_of_a_local_is_its_binding() {
+        let text = "x = 1\ng = \\x -> x + 1\nh = x\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let parameter = Some(Range::new(Position::new(1, 5), Position::new(1, 6)));
+        assert_eq!(definition_range(text, &tokens, &module, 1, 10), parameter);
+        assert_eq!(definition_range(text, &tokens, &module, 1, 5), parameter);
+        let top_level = Some(Range::new(Position::new(0, 0), Position::new(0, 1)));
+        assert_eq!(definition_range(text, &tokens, &module, 2, 4), top_level);
+    }
+
+    #[test]
+    fn missing_definition_is_none() {
+        let text = "main = answer\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        assert_eq!(definition_range(text, &tokens, &module, 0, 8), None);
+        // not an identifier
+        assert_eq!(definition_range(text, &tokens, &module, 0, 5), None);
+    }
+
+    #[test]
+    fn can_find_references() {
+        let text = "x = 1\ny = x\nz = f x\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let ranges = |found: Vec<&Token>| found.into_iter().map(|t| t.lsp_range(text)).collect::<Vec<_>>();
+        let at = |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        assert_eq!(
+            ranges(references(text, &tokens, &module, 1, 4, true)),
+            vec![at(0, 0, 1), at(1, 4, 5), at(2, 6, 7)]
+        );
+        assert_eq!(
+            ranges(references(text, &tokens, &module, 1, 4, false)),
+            vec![at(1, 4, 5), at(2, 6, 7)]
+        );
+    }
+
+    #[test]
+    fn rename_edits_every_occurrence() {
+        let text = "x = 1\ny = x\nz = f x\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let at = |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        let edits = rename_edits(text, &tokens, &module, 0, 0, "count").unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                TextEdit::new(at(0, 0, 1), "count".to_string()),
+                TextEdit::new(at(1, 4, 5), "count".to_string()),
+                TextEdit::new(at(2, 6, 7), "count".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn prepare_rename_covers_the_name() {
+        let text = "module Main where\nanswer = 42\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let answer = Range::new(Position::new(1, 0), Position::new(1, 6));
+        assert_eq!(prepare_rename_range(text, &tokens, &module, 1, 2).unwrap(), answer);
+        assert_eq!(prepare_rename_range(text, &tokens, &module, 1, 6).unwrap(), answer);
+    }
+
+    #[test]
+    fn prepare_rename_rejects_everything_but_names() {
+        let text = "module Main where\nanswer = 42\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        for (line, character) in [(0, 2), (0, 8), (1, 8), (1, 10)] {
+            assert!(prepare_rename_range(text, &tokens, &module, line, character).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_command_lists_errors() {
+        assert_eq!(parse_command("main = 1\nbad = = 2\n"), Err("2:7: expected an expression\n".to_string()));
+        assert!(parse_command("main = 1\n").unwrap().contains("name: \"main\""));
+        assert_eq!(lex_command("x = @\n"), Err("1:5: unexpected character `@`\n".to_string()));
+    }
+
+    #[test]
+    fn rename_refuses_to_capture_a_use() {
+        let text = "x = 1\nf =\n  let y = 2\n  in x + y\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let refused = rename_edits(text, &tokens, &module, 0, 0, "y").unwrap_err();
+        assert_eq!(refused.message, "renaming `x` to `y` would capture it in the `y` bound at 3:7");
+        assert!(rename_edits(text, &tokens, &module, 0, 0, "z").is_ok());
+    }
+
+    #[test]
+    fn rename_refuses_to_clash_with_a_definition() {
+        let text = "x = 1\ny = 2\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let refused = rename_edits(text, &tokens, &module, 0, 0, "y").unwrap_err();
+        assert_eq!(refused.message, "`y` is already defined at 2:1");
+        assert!(rename_edits(text, &tokens, &module, 0, 0, "x").is_ok());
+    }
+
+    #[test]
+    fn rename_skips_a_shadowing_lambda_parameter() {
+        let text = "x = 1\ng = \\x -> x + 1\nh = x\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let at = |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        let edits = rename_edits(text, &tokens, &module, 0, 0, "count").unwrap();
+        assert_eq!(
+            edits,
+            vec![TextEdit::new(at(0, 0, 1), "count".to_string()), TextEdit::new(at(2, 4, 5), "count".to_string())]
+        );
+        // the parameter only reaches its own uses, and can't be renamed
+        let ranges: Vec<_> = references(text, &tokens, &module, 1, 10, true).into_iter().map(|t| t.lsp_range(text)).collect();
+        assert_eq!(ranges, vec![at(1, 5, 6), at(1, 10, 11)]);
+        let refused = rename_edits(text, &tokens, &module, 1, 5, "y").unwrap_err();
+        assert_eq!(refused.message, "`x` is local, and only top-level definitions can be renamed");
+        assert!(prepare_rename_range(text, &tokens, &module, 1, 10).is_err());
+    }
+
+    #[test]
+    fn rename_rejects_invalid_identifiers() {
+        let text = "x = 1\n";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        assert!(rename_edits(text, &tokens, &module, 0, 0, "Count").is_err());
+        assert!(rename_edits(text, &tokens, &module, 0, 0, "two words").is_err());
+        assert!(rename_edits(text, &tokens, &module, 0, 0, "where").is_err());
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|i| i.label.as_str()).collect()
+    }
+
+    #[test]
+    fn completions_offer_definitions_and_keywords() {
+        let text = "foo = 1\nbar x = x\nbaz = ";
+        let tokens = lex_line(text).unwrap();
+        let items = completions(text, &tokens, &Module::new(text), 2, 6);
+        let found = labels(&items);
+        assert!(found.contains(&"foo"));
+        assert!(found.contains(&"bar"));
+        assert!(found.contains(&"let"));
+        assert_eq!(items[0].kind, Some(CompletionItemKind::CONSTANT));
+        assert_eq!(items[1].kind, Some(CompletionItemKind::FUNCTION));
+    }
+
+    #[test]
+    fn completions_after_dot_are_scoped_to_module() {
+        let text = "module Foo where\nfoo = 1\nbar = Foo.";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(labels(&completions(text, &tokens, &Module::new(text), 2, 10)), vec!["foo", "bar"]);
+        let text = "module Foo where\nfoo = 1\nbar = Other.";
+        let tokens = lex_line(text).unwrap();
+        assert!(completions(text, &tokens, &Module::new(text), 2, 12).is_empty());
+    }
+
+    #[test]
+    fn resolving_a_completion_describes_the_definition() {
+        let text = "-- Pairs two values\n{- in order -}\npair x y = (x, y)\n\nanswer = pair 1 \"one\"\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let symbols = Module::new(text);
+        let item = CompletionItem { label: "pair".to_string(), ..CompletionItem::default() };
+        let resolved = resolve_completion(&tokens, &analysed, &symbols, item);
+        assert_eq!(resolved.label, "pair");
+        assert_eq!(resolved.detail.as_deref(), Some("Int -> String -> (Int, String)"));
+        assert_eq!(resolved.documentation, Some(Documentation::String("Pairs two values\nin order".to_string())));
+
+        let item = CompletionItem { label: "answer".to_string(), ..CompletionItem::default() };
+        let resolved = resolve_completion(&tokens, &analysed, &symbols, item);
+        assert_eq!((resolved.detail.as_deref(), resolved.documentation), (Some("(Int, String)"), None));
+    }
+
+    #[test]
+    fn doc_comments_sit_above_the_signature() {
+        let text = "-- The answer\nanswer : Int\nanswer = 42\n";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(doc_comment(&tokens, "answer").as_deref(), Some("The answer"));
+    }
+
+    #[tokio::test]
+    async fn completions_resolve_against_their_document() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "-- Always 42\nanswer = 42\nmain = \n")).await;
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(2, 7),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        };
+        let Some(CompletionResponse::Array(items)) = backend.completion(params).await.unwrap() else {
+            panic!("expected completions");
+        };
+        let answer = items.into_iter().find(|i| i.label == "answer").unwrap();
+        let resolved = backend.completion_resolve(answer).await.unwrap();
+        assert_eq!(resolved.detail.as_deref(), Some("Int"));
+        assert_eq!(resolved.documentation, Some(Documentation::String("Always 42".to_string())));
+
+        let keyword = CompletionItem { label: "let".to_string(), ..CompletionItem::default() };
+        assert_eq!(backend.completion_resolve(keyword.clone()).await.unwrap(), keyword);
+    }
+
+    #[tokio::test]
+    async fn completions_resolve_in_documents_that_dont_lex() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "-- Always 42\nanswer = 42\nmain = answer ` 1\n")).await;
+        let item = CompletionItem {
+            label: "answer".to_string(),
+            data: Some(uri.to_string().into()),
+            ..CompletionItem::default()
+        };
+        let resolved = backend.completion_resolve(item).await.unwrap();
+        assert_eq!(resolved.detail.as_deref(), Some("Int"));
+        assert_eq!(resolved.documentation, Some(Documentation::String("Always 42".to_string())));
+    }
+
+    #[test]
+    fn hover_describes_definitions() {
+        let text = "answer = 42\nadd x y = x\nmain = add answer 1\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let (message, range) = hover_message(text, &tokens, &analysed, &Module::new(text), 2, 12).unwrap();
+        assert!(message.description.contains("answer"));
+        assert_eq!(range, Range::new(Position::new(2, 11), Position::new(2, 17)));
+        let (message, _) = hover_message(text, &tokens, &analysed, &Module::new(text), 2, 8).unwrap();
+        assert!(message.description.contains("add x y"));
+    }
+
+    #[test]
+    fn hover_describes_locals_as_local() {
+        let text = "x = 1\ng = \\x -> x + 1\nh = x\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let (message, _) = hover_message(text, &tokens, &analysed, &Module::new(text), 1, 10).unwrap();
+        assert_eq!(message.description, "`x` is a local binding");
+        let (message, _) = hover_message(text, &tokens, &analysed, &Module::new(text), 2, 4).unwrap();
+        assert_eq!(message.description, "`x` is a top-level definition");
+    }
+
+    #[test]
+    fn hover_on_a_partial_application_expects_more_arguments() {
+        // signatures can't name types yet, so `f : Int -> String -> [(Int, String)]` is inferred
+        let text = "f a b = [(a, b), (1, \"s\")]\nmain = f 1\nboth = f 1 \"t\"\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let symbols = Module::new(text);
+        let (message, _) = hover_message(text, &tokens, &analysed, &symbols, 1, 7).unwrap();
+        assert_eq!(
+            message.description,
+            "`f a b` is a top-level function of type `Int -> String -> [(Int, String)]`; \
+             here it expects 1 more argument, of type `String`"
+        );
+        let (message, _) = hover_message(text, &tokens, &analysed, &symbols, 2, 7).unwrap();
+        assert_eq!(message.description, "`f a b` is a top-level function of type `Int -> String -> [(Int, String)]`");
+    }
+
+    #[test]
+    fn hover_describes_keywords() {
+        let text = "module Foo where\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let (message, _) = hover_message(text, &tokens, &analysed, &Module::new(text), 0, 2).unwrap();
+        assert!(message.description.contains("module"));
+    }
+
+    #[test]
+    fn hover_renders_markdown_for_string_literals() {
+        let text = "greeting = \"hi\"\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let (message, range) = hover_message(text, &tokens, &analysed, &Module::new(text), 0, 12).unwrap();
+        assert_eq!(range, Range::new(Position::new(0, 11), Position::new(0, 15)));
+        assert_eq!(
+            hover_contents(message, true),
+            HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "```saffron\n\"hi\" : String\n```\na string literal".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn hover_falls_back_to_plain_text() {
+        let hover = HoverText { code: "answer".to_string(), description: "`answer` is a top-level definition".to_string() };
+        assert_eq!(
+            hover_contents(hover, false),
+            HoverContents::Scalar(MarkedString::String("`answer` is a top-level definition".to_string()))
+        );
+    }
+
+    #[test]
+    fn markdown_hover_is_negotiated_with_the_client() {
+        let mut params = InitializeParams::default();
+        assert!(!Settings::from_params(&params).markdown_hover);
+        params.capabilities.text_document = Some(TextDocumentClientCapabilities {
+            hover: Some(HoverClientCapabilities {
+                dynamic_registration: None,
+                content_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
+            }),
+            ..TextDocumentClientCapabilities::default()
+        });
+        assert!(Settings::from_params(&params).markdown_hover);
+    }
+
+    #[test]
+    fn positions_after_an_emoji_count_utf16() {
+        let text = "main = \"😀\" ++ answer\nanswer = 1\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let (message, range) = hover_message(text, &tokens, &analysed, &Module::new(text), 0, 16).unwrap();
+        assert_eq!(message.code, "answer");
+        assert_eq!(range, Range::new(Position::new(0, 15), Position::new(0, 21)));
+        let highlighted = semantic_tokens(text, &tokenize_document(text));
+        assert_eq!(highlighted[2], semantic(0, 2, 4, SemanticTokenType::STRING));
+        assert_eq!(highlighted[3], semantic(0, 5, 2, SemanticTokenType::OPERATOR));
+    }
+
+    #[test]
+    fn hover_on_unknown_symbol_is_none() {
+        let text = "main = missing 1\n";
+        let tokens = lex_line(text).unwrap();
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        assert_eq!(hover_message(text, &tokens, &analysed, &Module::new(text), 0, 9), None);
+        assert_eq!(hover_message(text, &tokens, &analysed, &Module::new(text), 0, 15), None);
+    }
+
+    #[tokio::test]
+    async fn hover_falls_back_when_unsure() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "main = missing")).await;
+        let hover = backend.hover(hover_params(&uri, 0, 9)).await.unwrap().unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("Not sure what this is".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn opening_a_document_fills_its_symbol_store() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "answer = 42\nadd x y = x\nanswer = 1\nmain = add @ answer\n")).await;
+        let symbols = backend.symbols(&uri).unwrap();
+        assert_eq!(symbols.names, vec!["answer", "add", "main"]);
+        assert_eq!(symbols.get("answer"), Some(&Value::Constant));
+        assert_eq!(symbols.get("add"), Some(&Value::Function(vec!["x".to_string(), "y".to_string()])));
+        assert_eq!(symbols.get("x"), None);
+
+        backend
+            .did_change(change_params(&uri, vec![ranged_change((1, 0), (1, 3), "plus")]))
+            .await;
+        let symbols = backend.symbols(&uri).unwrap();
+        assert_eq!(symbols.names, vec!["answer", "plus", "main"]);
+        assert_eq!(symbols.get("add"), None);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_cached_until_the_document_changes() {
+        let backend = test_backend();
+        let uri = test_uri();
+        let cached = || backend.documents.lock().unwrap()[&uri].snapshot.clone();
+
+        backend.did_open(open_params(&uri, "answer = 42\nmain = answer")).await;
+        backend.hover(hover_params(&uri, 1, 8)).await.unwrap();
+        let (version, first) = cached().unwrap();
+        assert_eq!(version, 0);
+        backend.hover(hover_params(&uri, 0, 2)).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &cached().unwrap().1));
+
+        backend
+            .did_change(change_params(&uri, vec![ranged_change((0, 0), (0, 6), "reply")]))
+            .await;
+        assert!(cached().is_none());
+        let hover = backend.hover(hover_params(&uri, 0, 2)).await.unwrap().unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("`reply` is a top-level definition".to_string()))
+        );
+        let (version, second) = cached().unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(second.text(), "reply = 42\nmain = answer");
+    }
+
+    #[tokio::test]
+    async fn documents_that_dont_lex_are_still_served_but_not_formatted() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.did_open(open_params(&uri, "answer = 42\nmain = answer ` 1")).await;
+        let hover = backend.hover(hover_params(&uri, 1, 8)).await.unwrap().unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("`answer` is a top-level definition".to_string()))
+        );
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        assert_eq!(backend.formatting(params).await.unwrap(), None);
+    }
+
+    #[test]
+    fn selection_grows_from_token_to_document() {
+        let text = "x = 1\nf y = g (h (y))\n";
+        let tokens = lex_line(text).unwrap();
+        let spans = selection_spans(text, &tokens, 18);
+        let covered: Vec<_> = spans.iter().map(|&(start, end)| &text[start..end]).collect();
+        assert_eq!(covered, vec!["y", "(y)", "(h (y))", "g (h (y))", "f y = g (h (y))", text]);
+    }
+
+    #[test]
+    fn selection_ranges_are_nested() {
+        let text = "x = 1\nf y = g (h (y))\n";
+        let tokens = lex_line(text).unwrap();
+        let contains = |outer: &Range, inner: &Range| outer.start <= inner.start && inner.end <= outer.end;
+        let mut child = selection_range(text, &tokens, Position::new(1, 12));
+        let mut depth = 1;
+        while let Some(parent) = child.parent {
+            assert!(contains(&parent.range, &child.range));
+            assert_ne!(parent.range, child.range);
+            child = *parent;
+            depth += 1;
+        }
+        assert_eq!(depth, 6);
+    }
+
+    #[test]
+    fn can_fold_module_and_multi_line_definitions() {
+        let text = "module Foo where\n\nadd x y =\n  x + y\n\nanswer = 42\n\nmain =\n  add\n    answer\n    1\n";
+        let tokens = lex_line(text).unwrap();
+        let lines: Vec<_> = folding_ranges(text, &tokens)
+            .into_iter()
+            .map(|f| (f.start_line, f.end_line))
+            .collect();
+        assert_eq!(lines, vec![(0, 10), (2, 3), (7, 10)]);
+    }
+
+    fn line_range(line: u32) -> Range {
+        Range::new(Position::new(line, 0), Position::new(line, 0))
+    }
+
+    fn action_edits(action: &CodeActionOrCommand) -> Vec<TextEdit> {
+        let CodeActionOrCommand::CodeAction(action) = action else {
+            panic!("expected a code action, got {:?}", action);
+        };
+        let changes = action.edit.as_ref().and_then(|edit| edit.changes.as_ref()).unwrap();
+        changes.values().flatten().cloned().collect()
+    }
+
+    #[test]
+    fn can_add_a_missing_signature() {
+        let uri = test_uri();
+        let text = "module Main where\n\nanswer : Int\nanswer = 42\nzero = 0\n";
+        let (module, _) = saffron::Module::parse(text);
+        let actions = missing_signature_actions(text, &module, &uri, line_range(4));
+        assert_eq!(actions.len(), 1);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else { unreachable!() };
+        assert_eq!(action.title, "Add a type signature for `zero`");
+        assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+        assert_eq!(action_edits(&actions[0]), vec![TextEdit::new(line_range(4), "zero : _\n".to_string())]);
+    }
+
+    #[test]
+    fn signatures_are_only_offered_where_missing() {
+        let uri = test_uri();
+        let text = "answer : Int\nanswer = 42\nzero = 0\n";
+        let (module, _) = saffron::Module::parse(text);
+        assert_eq!(missing_signature_actions(text, &module, &uri, line_range(1)), vec![]);
+        let everything = Range::new(Position::new(0, 0), Position::new(3, 0));
+        assert_eq!(missing_signature_actions(text, &module, &uri, everything).len(), 1);
+    }
+
+    /// The document after applying the only action offered at `position`
+    fn after_action(text: &str, position: (u32, u32)) -> String {
+        let uri = test_uri();
+        let at = Position::new(position.0, position.1);
+        let (module, _) = saffron::Module::parse(text);
+        let actions = unused_import_actions(text, &module, &uri, Range::new(at, at));
+        assert_eq!(actions.len(), 1);
+        let mut text = text.to_string();
+        for edit in action_edits(&actions[0]) {
+            apply_edit(&mut text, edit.range, &edit.new_text);
+        }
+        text
+    }
+
+    #[test]
+    fn can_remove_one_unused_name() {
+        let text = "import Foo (bar, baz)\nmain = bar\n";
+        assert_eq!(after_action(text, (0, 18)), "import Foo (bar)\nmain = bar\n");
+        let text = "import Foo (bar, baz)\nmain = baz\n";
+        assert_eq!(after_action(text, (0, 13)), "import Foo (baz)\nmain = baz\n");
+    }
+
+    #[test]
+    fn removing_the_sole_name_removes_the_import() {
+        let text = "import Foo (bar)\nimport Baz (qux)\nmain = qux\n";
+        assert_eq!(after_action(text, (0, 12)), "import Baz (qux)\nmain = qux\n");
+    }
+
+    #[test]
+    fn used_imports_have_no_removal() {
+        let uri = test_uri();
+        let text = "import Foo (bar)\nmain = bar\n";
+        let (module, _) = saffron::Module::parse(text);
+        assert_eq!(unused_import_actions(text, &module, &uri, line_range(0)), vec![]);
+    }
+
+    #[test]
+    fn literal_definitions_get_type_hints() {
+        let text = "module Main where\nanswer = 42\ngreeting = \"hi\"\nid = \\x -> x\n";
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        let everything = Range::new(Position::new(0, 0), Position::new(4, 0));
+        let hints: Vec<_> = inlay_hints(text, &analysed, everything)
+            .into_iter()
+            .map(|hint| match hint.label {
+                InlayHintLabel::String(label) => (hint.position, label),
+                InlayHintLabel::LabelParts(_) => panic!("expected a plain label"),
+            })
+            .collect();
+        assert_eq!(
+            hints,
+            vec![(Position::new(1, 6), ": Int".to_string()), (Position::new(2, 8), ": String".to_string())]
+        );
+    }
+
+    #[test]
+    fn signatures_suppress_type_hints() {
+        let text = "answer : Int\nanswer = 42\n";
+        let analysed = Analysed::new(text, parsing::DEFAULT_TAB_WIDTH);
+        assert!(inlay_hints(text, &analysed, Range::new(Position::new(0, 0), Position::new(2, 0))).is_empty());
+    }
+
+    #[test]
+    fn imports_link_to_their_module_files() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two_modules");
+        let uri = Url::from_file_path(root.join("Main.saffron")).unwrap();
+        let text = "module Main where\n\nimport Data.List (map)\nimport Data.Missing\n";
+        let (module, _) = saffron::Module::parse(text);
+        let links = document_links(text, &module, &uri);
+        let targets: Vec<_> = links.iter().map(|link| (link.range, link.target.clone())).collect();
+        assert_eq!(
+            targets,
+            vec![
+                (
+                    Range::new(Position::new(2, 7), Position::new(2, 16)),
+                    Some(Url::from_file_path(root.join("Data").join("List.saffron")).unwrap())
+                ),
+                (Range::new(Position::new(3, 7), Position::new(3, 19)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn untitled_documents_have_no_link_targets() {
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        let (module, _) = saffron::Module::parse("import Data.List\n");
+        let links = document_links("import Data.List\n", &module, &uri);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target, None);
+    }
+
+    fn semantic(delta_line: u32, delta_start: u32, length: u32, token_type: SemanticTokenType) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: SEMANTIC_TOKEN_TYPES.iter().position(|t| *t == token_type).unwrap() as u32,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn can_encode_semantic_tokens() {
+        let text = "module foo = \"bar\"";
+        assert_eq!(
+            semantic_tokens(text, &tokenize_document(text)),
+            vec![
+                semantic(0, 0, 6, SemanticTokenType::KEYWORD),
+                semantic(0, 7, 3, SemanticTokenType::VARIABLE),
+                semantic(0, 4, 1, SemanticTokenType::OPERATOR),
+                semantic(0, 2, 5, SemanticTokenType::STRING),
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_reset_start_on_new_lines() {
+        let text = "x = 1\n  y = {- a\nb -} 2";
+        assert_eq!(
+            semantic_tokens(text, &tokenize_document(text)),
+            vec![
+                semantic(0, 0, 1, SemanticTokenType::VARIABLE),
+                semantic(0, 2, 1, SemanticTokenType::OPERATOR),
+                semantic(0, 2, 1, SemanticTokenType::NUMBER),
+                semantic(1, 2, 1, SemanticTokenType::VARIABLE),
+                semantic(0, 2, 1, SemanticTokenType::OPERATOR),
+                semantic(0, 2, 4, SemanticTokenType::COMMENT),
+                semantic(1, 0, 4, SemanticTokenType::COMMENT),
+                semantic(0, 5, 1, SemanticTokenType::NUMBER),
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_leave_out_carriage_returns() {
+        let text = "{- a\r\nb -}\r\nx";
+        assert_eq!(
+            semantic_tokens(text, &tokenize_document(text)),
+            vec![
+                semantic(0, 0, 4, SemanticTokenType::COMMENT),
+                semantic(1, 0, 4, SemanticTokenType::COMMENT),
+                semantic(1, 0, 1, SemanticTokenType::VARIABLE),
+            ]
+        );
+    }
+
+    #[test]
+    fn signature_help_tracks_active_parameter() {
+        let text = "f x y = x\nmain = f x ";
+        let tokens = lex_line(text).unwrap();
+        let help = signature_help(text, &tokens, 1, 11).unwrap();
+        assert_eq!(help.signatures[0].label, "f x y");
+        assert_eq!(help.active_parameter, Some(1));
+        let help = signature_help(text, &tokens, 1, 10).unwrap();
+        assert_eq!(help.active_parameter, Some(0));
+    }
+
+    #[test]
+    fn signature_help_uses_innermost_application() {
+        let text = "f x y = x\ng a = a\nmain = f (g 1) (g ";
+        let tokens = lex_line(text).unwrap();
+        let help = signature_help(text, &tokens, 2, 18).unwrap();
+        assert_eq!(help.signatures[0].label, "g a");
+        assert_eq!(help.active_parameter, Some(0));
+        let help = signature_help(text, &tokens, 2, 15).unwrap();
+        assert_eq!(help.signatures[0].label, "f x y");
+        assert_eq!(help.active_parameter, Some(1));
+        assert_eq!(signature_help(text, &tokens, 2, 6), None);
+    }
+
+    #[test]
+    fn highlights_mark_definition_as_write() {
+        let text = "double = add 1\nx = double 2\ny = double x";
+        let tokens = lex_line(text).unwrap();
+        let (module, _) = saffron::Module::parse(text);
+        let highlights = document_highlights(text, &tokens, &module, 1, 6);
+        let kinds: Vec<_> = highlights.iter().map(|h| (h.range.start, h.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Position::new(0, 0), Some(DocumentHighlightKind::WRITE)),
+                (Position::new(1, 4), Some(DocumentHighlightKind::READ)),
+                (Position::new(2, 4), Some(DocumentHighlightKind::READ)),
+            ]
+        );
+    }
+
+    #[test]
+    fn formatting_normalizes_spacing_and_blank_lines() {
+        let text = "module   Foo  where\n\n\n\nx=1   \ny   =  \"a  b\"\n  z   {- a  -}\n\n\n";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(
+            format_document(text, &tokens, 4),
+            "module Foo where\n\nx = 1\n\ny = \"a  b\"\n  z {- a  -}\n"
+        );
+    }
+
+    #[test]
+    fn formatting_expands_tabs_in_indentation() {
+        let text = "f x =\n\tg x\n  \th\t x\n";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(format_document(text, &tokens, 4), "f x =\n    g x\n    h x\n");
+        assert_eq!(format_document(text, &tokens, 2), "f x =\n  g x\n    h x\n");
+    }
+
+    fn initialize_params(options: serde_json::Value) -> InitializeParams {
+        InitializeParams {
+            initialization_options: Some(options),
+            ..InitializeParams::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn tab_width_comes_from_initialization_options() {
+        let backend = test_backend();
+        assert_eq!(backend.settings().tab_width, parsing::DEFAULT_TAB_WIDTH);
+        backend.initialize(initialize_params(serde_json::json!({ "tabWidth": 8 }))).await.unwrap();
+        assert_eq!(backend.settings().tab_width, 8);
+    }
+
+    #[test]
+    fn settings_ignore_nonsense_values() {
+        let params = initialize_params(serde_json::json!({ "tabWidth": 0, "maxLineLength": "long" }));
+        assert_eq!(Settings::from_params(&params), Settings::default());
+    }
+
+    #[tokio::test]
+    async fn checks_are_reported_at_the_configured_severity() {
+        let backend = test_backend();
+        let uri = test_uri();
+        let options = serde_json::json!({ "diagnostics": { "unusedImport": "WARNING", "undefinedName": "off" } });
+        backend.initialize(initialize_params(options)).await.unwrap();
+        let text = "module Main where\nimport Data.List (map)\nmain : Int\nmain = missing\n";
+        backend.did_open(open_params(&uri, text)).await;
+        let (diagnostics, _) = backend.saved_diagnostics(&uri).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`map` is imported but never used");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+    }
+
+    #[test]
+    fn unknown_options_are_ignored() {
+        let params = initialize_params(serde_json::json!({
+            "tabWidth": 2,
+            "colour": "orange",
+            "diagnostics": { "unusedImport": "hint", "spelling": "error" },
+        }));
+        assert_eq!(Settings::from_params(&params).tab_width, 2);
+        assert_eq!(Settings::ignored_options(&params), vec!["colour", "diagnostics.spelling"]);
+    }
+
+    #[test]
+    fn oversized_documents_get_a_note_instead_of_diagnostics() {
+        let settings = Settings { max_document_size: 10, ..Settings::default() };
+        let diagnostics = change_diagnostics("answer = 42 @", &test_uri(), &settings);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::INFORMATION));
+        assert_eq!(diagnostics[0].message, "documents over 10 bytes aren't analysed");
+
+        let settings = Settings { max_line_length: 5, ..Settings::default() };
+        let diagnostics = change_diagnostics("x = 1\nanswer = 42", &test_uri(), &settings);
+        assert_eq!(diagnostics[0].message, "documents with lines over 5 bytes aren't analysed");
+        assert_eq!(change_diagnostics("x = 1\ny = 2", &test_uri(), &settings), vec![]);
+    }
+
+    #[tokio::test]
+    async fn oversized_documents_are_never_lexed() {
+        let backend = test_backend();
+        let uri = test_uri();
+        backend.initialize(initialize_params(serde_json::json!({ "maxDocumentSize": 8 }))).await.unwrap();
+        backend.did_open(open_params(&uri, "answer = 42")).await;
+        assert_eq!(backend.hover(hover_params(&uri, 0, 2)).await.unwrap(), None);
+        assert!(backend.documents.lock().unwrap()[&uri].snapshot.is_none());
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let text = "module Foo where\n\n\nf   x=x  \n-- about g\ng = f   1\n    2   \n\nh=g\n";
+        let once = format_document(text, &lex_line(text).unwrap(), 4);
+        let twice = format_document(&once, &lex_line(&once).unwrap(), 4);
+        assert_eq!(once, "module Foo where\n\nf x = x\n-- about g\ng = f 1\n    2\n\nh = g\n");
+        assert_eq!(twice, once);
+    }
+
+    #[test]
+    fn token_at_finds_token_containing_cursor() {
+        let text = "foo = bar";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(token_at(text, &tokens, 0, 0).map(|t| &t.content), Some(&TokenContent::Ident("foo")));
+        assert_eq!(token_at(text, &tokens, 0, 2).map(|t| &t.content), Some(&TokenContent::Ident("foo")));
+        assert_eq!(token_at(text, &tokens, 0, 3).map(|t| &t.content), Some(&TokenContent::Space(1)));
+        assert_eq!(token_at(text, &tokens, 0, 4).map(|t| &t.content), Some(&TokenContent::Equals));
+        assert_eq!(token_at(text, &tokens, 0, 8).map(|t| &t.content), Some(&TokenContent::Ident("bar")));
+        assert_eq!(token_at(text, &tokens, 0, 9), None);
+    }
+
+    #[test]
+    fn token_at_respects_line() {
+        let text = "foo = bar\nbaz\n\n  qux";
+        let tokens = lex_line(text).unwrap();
+        assert_eq!(token_at(text, &tokens, 1, 1).map(|t| &t.content), Some(&TokenContent::Ident("baz")));
+        assert_eq!(token_at(text, &tokens, 3, 2).map(|t| &t.content), Some(&TokenContent::Ident("qux")));
+        assert_eq!(token_at(text, &tokens, 4, 0), None);
+    }
+
+    #[test]
+    fn token_at_finds_tokens_spanning_lines() {
+        let text = "{- one\ntwo -} foo";
+        let tokens = lex_line(text).unwrap();
+        let comment = token_at(text, &tokens, 1, 1).unwrap();
+        assert_eq!(comment.content, TokenContent::BlockComment(" one\ntwo "));
+        assert_eq!(comment.lsp_range(text), Range::new(Position::new(0, 0), Position::new(1, 6)));
+    }
+}
@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::{Diagnostic, DiagnosticSeverity};
+use crate::parsing::{parse_declarations, Expr, DEFAULT_TAB_WIDTH, Fixity, ModuleHeader, Signature, Spanned, TopLevel};
+
+/// The extension of Saffron source files
+pub const SOURCE_EXTENSION: &str = "saffron";
+
+/// A named collection of modules, along with the packages it imports
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Package {
+    pub name: String,
+    pub local_modules: Vec<Module>,
+    pub imported_packages: Vec<Package>,
+}
+
+/// A single source file: its definitions and what it imports
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Module {
+    pub name: String,
+    /// Where the name is written in the module header, if there is one
+    pub name_span: Option<Range<usize>>,
+    pub members: Vec<Spanned<Definition>>,
+    /// The names the header's export list makes visible to importers. A
+    /// module without an export list exports everything it defines.
+    pub exports: Option<Vec<ImportedName>>,
+    pub imports: Vec<Import>,
+    /// The fixities the module declares for its operators, in order
+    pub fixities: Vec<Fixity>,
+    /// The file the module was loaded from, if it came from one
+    pub path: Option<PathBuf>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ModuleName {
+    pub value: String,
+    pub span: Range<usize>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Import {
+    pub name: ModuleName,
+    pub reference: ModuleReference,
+    /// From `import` to the end of the imported names
+    pub span: Range<usize>,
+}
+
+/// Which members of a module an import brings into scope
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ModuleReference {
+    WildCard,
+    Single(ImportedName),
+    Many(Vec<ImportedName>)
+}
+
+/// A name listed in an import or export list, and where it was listed
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ImportedName {
+    pub name: String,
+    pub span: Range<usize>,
+}
+
+/// A top-level `name = expr` definition
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Definition {
+    pub name: String,
+    pub name_span: Range<usize>,
+    /// The type from the definition's signature, if it has one
+    pub def_type: Option<Expr>,
+    pub def_expr: Expr
+}
+
+
+impl Module {
+    /// Parses a whole source file, reporting every problem found along the
+    /// way
+    pub fn parse(text: &str) -> (Module, Vec<Diagnostic>) {
+        Module::parse_with_tab_width(text, DEFAULT_TAB_WIDTH)
+    }
+
+    /// Parses a whole source file like `parse`, lining up indented blocks
+    /// with tabs `tab_width` columns wide
+    pub fn parse_with_tab_width(text: &str, tab_width: usize) -> (Module, Vec<Diagnostic>) {
+        let (declarations, errors) = parse_declarations(text, tab_width);
+        let (module, mut diagnostics) = Module::from_declarations(declarations);
+        diagnostics.extend(errors.iter().map(Diagnostic::from));
+        diagnostics.sort_by_key(|d| d.span.start);
+        (module, diagnostics)
+    }
+
+    /// Collects parsed declarations into a module, giving each definition the
+    /// type from the signature of the same name before it. Signatures
+    /// without a definition are errors; definitions without a signature are
+    /// warned about, as are exported names that aren't defined. A module
+    /// without a header is called `Main`.
+    pub fn from_declarations(declarations: Vec<TopLevel>) -> (Module, Vec<Diagnostic>) {
+        let mut diagnostics = vec![];
+        let mut header = None;
+        let mut signatures: HashMap<String, Signature> = HashMap::new();
+        let mut members = vec![];
+        let mut imports = vec![];
+        let mut fixities = vec![];
+        for (i, declaration) in declarations.into_iter().enumerate() {
+            match declaration {
+                TopLevel::Header(ModuleHeader { name, name_span, exports }) if i == 0 => {
+                    header = Some((name, name_span, exports))
+                }
+                TopLevel::Header(ModuleHeader { name_span, .. }) => diagnostics.push(Diagnostic::error(
+                    name_span,
+                    "the module header must come before everything else",
+                )),
+                TopLevel::Import(import) => imports.push(import),
+                TopLevel::Fixity(fixity) => fixities.push(fixity),
+                TopLevel::Signature(signature) => {
+                    signatures.insert(signature.name.clone(), signature);
+                }
+                TopLevel::Definition(mut definition) => {
+                    match signatures.remove(&definition.name) {
+                        Some(signature) => definition.def_type = Some(signature.ty),
+                        None => diagnostics.push(Diagnostic::warning(
+                            definition.name_span.clone(),
+                            format!("`{}` has no type signature", definition.name),
+                        )),
+                    }
+                    members.push(definition);
+                }
+            }
+        }
+        let mut unmatched: Vec<_> = signatures.into_values().collect();
+        unmatched.sort_by_key(|signature| signature.name_span.start);
+        diagnostics.extend(unmatched.into_iter().map(|signature| {
+            Diagnostic::error(
+                signature.name_span,
+                format!("`{}` has a type signature but no definition", signature.name),
+            )
+        }));
+        let (name, name_span, exports) = match header {
+            Some((name, span, exports)) => (name, Some(span), exports),
+            None => ("Main".to_string(), None, None),
+        };
+        for exported in exports.iter().flatten() {
+            if !members.iter().any(|d| d.name == exported.name) {
+                diagnostics.push(Diagnostic::error(
+                    exported.span.clone(),
+                    format!("`{}` is exported but never defined", exported.name),
+                ));
+            }
+        }
+        (Module { name, name_span, members, exports, imports, fixities, path: None }, diagnostics)
+    }
+
+    /// Whether importers can use `name`: it has to be defined here, and
+    /// listed in the export list if there is one
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.members.iter().any(|d| d.name == name)
+            && self.exports.as_ref().is_none_or(|exports| exports.iter().any(|e| e.name == name))
+    }
+}
+
+/// The module as pretty-printed JSON, for tools that can't link against
+/// this crate
+#[cfg(feature = "serde")]
+pub fn dump_ast(module: &Module) -> String {
+    serde_json::to_string_pretty(module).expect("modules only hold strings, numbers and lists")
+}
+
+impl Package {
+    /// The module called `name`, from this package or one it imports
+    pub fn find_module(&self, name: &str) -> Option<&Module> {
+        self.local_modules
+            .iter()
+            .find(|m| m.name == name)
+            .or_else(|| self.imported_packages.iter().find_map(|p| p.find_module(name)))
+    }
+
+    /// The module an import refers to
+    pub fn resolve(&self, import: &Import) -> Option<&Module> {
+        self.find_module(&import.name.value)
+    }
+
+    /// Reports every import of a module that doesn't exist, and every
+    /// imported name the module doesn't define or doesn't export
+    pub fn check_imports(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for module in &self.local_modules {
+            let located = |d: Diagnostic| Diagnostic { path: module.path.clone(), ..d };
+            for import in &module.imports {
+                let Some(target) = self.resolve(import) else {
+                    diagnostics.push(located(Diagnostic::error(
+                        import.name.span.clone(),
+                        format!("there's no module `{}`", import.name.value),
+                    )));
+                    continue;
+                };
+                let names = match &import.reference {
+                    ModuleReference::WildCard => vec![],
+                    ModuleReference::Single(name) => vec![name],
+                    ModuleReference::Many(names) => names.iter().collect(),
+                };
+                for imported in names {
+                    if !target.members.iter().any(|d| d.name == imported.name) {
+                        diagnostics.push(located(Diagnostic::error(
+                            imported.span.clone(),
+                            format!("`{}` doesn't define `{}`", target.name, imported.name),
+                        )));
+                    } else if !target.is_exported(&imported.name) {
+                        diagnostics.push(located(Diagnostic::error(
+                            imported.span.clone(),
+                            format!("`{}` doesn't export `{}`", target.name, imported.name),
+                        )));
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    /// Reports each cycle of local modules importing each other, on the
+    /// import that closes it
+    pub fn check_cycles(&self) -> Vec<Diagnostic> {
+        let mut done = vec![false; self.local_modules.len()];
+        let mut diagnostics = vec![];
+        for start in 0..self.local_modules.len() {
+            self.visit(start, &mut vec![], &mut done, &mut diagnostics);
+        }
+        diagnostics
+    }
+
+    /// Depth-first search from the module at `index`, with `stack` holding
+    /// the modules whose imports are still being followed
+    fn visit(&self, index: usize, stack: &mut Vec<usize>, done: &mut [bool], diagnostics: &mut Vec<Diagnostic>) {
+        if done[index] {
+            return;
+        }
+        stack.push(index);
+        let module = &self.local_modules[index];
+        for import in &module.imports {
+            let Some(next) = self.local_modules.iter().position(|m| m.name == import.name.value) else {
+                continue;
+            };
+            if let Some(from) = stack.iter().position(|&i| i == next) {
+                let cycle: Vec<_> = stack[from..]
+                    .iter()
+                    .chain([&next])
+                    .map(|&i| self.local_modules[i].name.as_str())
+                    .collect();
+                diagnostics.push(Diagnostic {
+                    path: module.path.clone(),
+                    ..Diagnostic::error(
+                        import.name.span.clone(),
+                        format!("modules import each other in a cycle: {}", cycle.join(" -> ")),
+                    )
+                });
+            } else {
+                self.visit(next, stack, done, diagnostics);
+            }
+        }
+        stack.pop();
+        done[index] = true;
+    }
+}
+
+/// Loads every `.saffron` file under `path`, however deeply nested, as a
+/// module of a package named after the directory. Fails with every error
+/// found if any file can't be read or has errors in it.
+pub fn load_package(path: &Path) -> Result<Package, Vec<Diagnostic>> {
+    let mut errors = vec![];
+    let mut files = vec![];
+    source_files(path, &mut files, &mut errors);
+    files.sort();
+
+    let mut local_modules = vec![];
+    for file in files {
+        match read_source(&file) {
+            Ok(text) => {
+                let (module, diagnostics) = Module::parse(&text);
+                errors.extend(
+                    diagnostics
+                        .into_iter()
+                        .chain(check_path(&module, path, &file))
+                        .filter(|d| d.severity == DiagnosticSeverity::ERROR)
+                        .map(|d| Diagnostic { path: Some(file.clone()), ..d }),
+                );
+                local_modules.push(Module { path: Some(file), ..module });
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let name = path
+        .canonicalize()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| path.display().to_string());
+    Ok(Package { name, local_modules, imported_packages: vec![] })
+}
+
+/// Where the module called `name` lives under a package's `root`, like
+/// `root/Data/List.saffron` for `Data.List`
+pub fn module_path(root: &Path, name: &str) -> PathBuf {
+    let mut path = root.join(name.split('.').collect::<PathBuf>());
+    path.set_extension(SOURCE_EXTENSION);
+    path
+}
+
+/// The root of the package that `file` belongs to, given the name of the
+/// module in it: the directory `Data/List.saffron` sits in for `Data.List`
+pub fn package_root(file: &Path, name: &str) -> Option<PathBuf> {
+    file.ancestors().nth(name.split('.').count()).map(Path::to_path_buf)
+}
+
+/// Reports a module whose header names it differently from where `file`
+/// sits under `root`, like `module Data.List` outside of `Data/List.saffron`.
+/// Modules without a header can live anywhere.
+fn check_path(module: &Module, root: &Path, file: &Path) -> Option<Diagnostic> {
+    let span = module.name_span.clone()?;
+    let relative = file.strip_prefix(root).ok()?.with_extension("");
+    let expected: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    if expected.join(".") == module.name {
+        return None;
+    }
+    let wanted = module_path(Path::new(""), &module.name);
+    Some(Diagnostic::error(
+        span,
+        format!("module `{}` should be in `{}`", module.name, wanted.display()),
+    ))
+}
+
+/// Collects the source files under `dir` into `files`
+fn source_files(dir: &Path, files: &mut Vec<PathBuf>, errors: &mut Vec<Diagnostic>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return errors.push(io_error(dir, e)),
+    };
+    for entry in entries {
+        match entry.map(|e| e.path()) {
+            Ok(path) if path.is_dir() => source_files(&path, files, errors),
+            Ok(path) if path.extension().is_some_and(|e| e == SOURCE_EXTENSION) => files.push(path),
+            Ok(_) => {}
+            Err(e) => errors.push(io_error(dir, e)),
+        }
+    }
+}
+
+/// The text of a source file, or why it couldn't be read. A file that isn't
+/// UTF-8 is reported at the first byte that isn't.
+fn read_source(file: &Path) -> Result<String, Diagnostic> {
+    let bytes = fs::read(file).map_err(|e| io_error(file, e))?;
+    String::from_utf8(bytes).map_err(|e| {
+        let at = e.utf8_error().valid_up_to();
+        Diagnostic {
+            path: Some(file.to_path_buf()),
+            ..Diagnostic::error(at..at + 1, format!("`{}` isn't valid UTF-8", file.display()))
+        }
+    })
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> Diagnostic {
+    Diagnostic {
+        path: Some(path.to_path_buf()),
+        ..Diagnostic::error(0..0, format!("couldn't read `{}`: {}", path.display(), error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse_top_level;
+
+    fn module(input: &str) -> (Module, Vec<Diagnostic>) {
+        Module::from_declarations(parse_top_level(input).unwrap())
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn can_load_package_from_directory() {
+        let package = load_package(&fixture("two_modules")).unwrap();
+        assert_eq!(package.name, "two_modules");
+        let names: Vec<_> = package.local_modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["Data.List", "Main"]);
+        assert_eq!(package.local_modules[1].imports[0].name.value, "Data.List");
+    }
+
+    #[test]
+    fn loading_reports_errors_per_file() {
+        let errors = load_package(&fixture("broken")).unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["`answer` has a type signature but no definition", "expected an expression"]
+        );
+        assert!(errors.iter().all(|e| e.path == Some(fixture("broken").join("Broken.saffron"))));
+    }
+
+    #[test]
+    fn files_must_be_utf8() {
+        let file = fixture("not_utf8").join("Latin1.saffron");
+        assert_eq!(
+            load_package(&fixture("not_utf8")).unwrap_err(),
+            vec![Diagnostic {
+                path: Some(file.clone()),
+                ..Diagnostic::error(32..33, format!("`{}` isn't valid UTF-8", file.display()))
+            }]
+        );
+    }
+
+    #[test]
+    fn header_after_byte_order_mark_is_found() {
+        let (module, diagnostics) = Module::parse("\u{feff}module Data.List where\nx : Int\nx = 1\n");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!((module.name.as_str(), module.name_span), ("Data.List", Some(10..19)));
+    }
+
+    #[test]
+    fn module_must_match_its_path() {
+        let errors = load_package(&fixture("misplaced")).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![Diagnostic {
+                path: Some(fixture("misplaced").join("Data").join("Map.saffron")),
+                ..Diagnostic::error(7..16, "module `Data.List` should be in `Data/List.saffron`")
+            }]
+        );
+    }
+
+    #[test]
+    fn module_paths_follow_their_names() {
+        let root = Path::new("pkg");
+        assert_eq!(module_path(root, "Data.List"), Path::new("pkg/Data/List.saffron"));
+        assert_eq!(package_root(&module_path(root, "Data.List"), "Data.List"), Some(root.to_path_buf()));
+        assert_eq!(package_root(Path::new("pkg/Main.saffron"), "Main"), Some(root.to_path_buf()));
+    }
+
+    #[test]
+    fn missing_directory_is_an_error() {
+        let errors = load_package(&fixture("missing")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("couldn't read"));
+    }
+
+    fn package(sources: &[&str]) -> Package {
+        Package {
+            name: "test".to_string(),
+            local_modules: sources.iter().map(|s| Module::parse(s).0).collect(),
+            imported_packages: vec![],
+        }
+    }
+
+    #[test]
+    fn imports_resolve_to_local_modules() {
+        let package = package(&["module A where\nimport B (bar)\nfoo = bar", "module B where\nbar = 1"]);
+        let import = &package.local_modules[0].imports[0];
+        assert_eq!(package.resolve(import).map(|m| m.name.as_str()), Some("B"));
+        assert_eq!(package.check_imports(), vec![]);
+    }
+
+    #[test]
+    fn imports_resolve_to_imported_packages() {
+        let mut package = package(&["module A where\nimport Base (id)\nfoo = id"]);
+        package.imported_packages.push(Package {
+            name: "base".to_string(),
+            local_modules: vec![Module::parse("module Base where\nid = \\x -> x").0],
+            imported_packages: vec![],
+        });
+        assert_eq!(package.check_imports(), vec![]);
+    }
+
+    #[test]
+    fn missing_imports_are_reported() {
+        let package = package(&["module A where\nimport B (bar, qux)\nimport C\nfoo = bar", "module B where\nbar = 1"]);
+        assert_eq!(
+            package.check_imports(),
+            vec![
+                Diagnostic::error(30..33, "`B` doesn't define `qux`"),
+                Diagnostic::error(42..43, "there's no module `C`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_exported_names_can_be_imported() {
+        let package = package(&[
+            "module A where\nimport B (bar, hidden)\nfoo = bar",
+            "module B (bar) where\nbar = hidden\nhidden = 1",
+        ]);
+        assert_eq!(package.check_imports(), vec![Diagnostic::error(30..36, "`B` doesn't export `hidden`")]);
+        let exported: Vec<_> = ["bar", "hidden", "missing"].into_iter().map(|n| package.local_modules[1].is_exported(n)).collect();
+        assert_eq!(exported, vec![true, false, false]);
+    }
+
+    #[test]
+    fn modules_without_an_export_list_export_everything() {
+        let (module, _) = module("module B where\nbar = 1\nbaz = 2");
+        assert_eq!(module.exports, None);
+        assert!(module.is_exported("bar") && module.is_exported("baz"));
+    }
+
+    #[test]
+    fn exported_names_must_be_defined() {
+        let (module, diagnostics) = module("module B (bar, qux) where\nbar : Int\nbar = 1");
+        assert_eq!(module.exports.as_ref().map(Vec::len), Some(2));
+        assert_eq!(diagnostics, vec![Diagnostic::error(15..18, "`qux` is exported but never defined")]);
+    }
+
+    #[test]
+    fn import_cycle_is_reported() {
+        let package = package(&["module A where\nimport B", "module B where\nimport A"]);
+        assert_eq!(
+            package.check_cycles(),
+            vec![Diagnostic::error(22..23, "modules import each other in a cycle: A -> B -> A")]
+        );
+    }
+
+    #[test]
+    fn longer_cycles_list_every_module() {
+        let package = package(&[
+            "module A where\nimport B",
+            "module B where\nimport C",
+            "module C where\nimport Data.List\nimport A",
+            "module Data.List where",
+        ]);
+        let messages: Vec<_> = package.check_cycles().into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, vec!["modules import each other in a cycle: A -> B -> C -> A"]);
+    }
+
+    #[test]
+    fn diamond_imports_are_not_a_cycle() {
+        let package = package(&[
+            "module A where\nimport B\nimport C",
+            "module B where\nimport D",
+            "module C where\nimport D",
+            "module D where",
+        ]);
+        assert_eq!(package.check_cycles(), vec![]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn can_dump_ast_as_json() {
+        let (module, _) = Module::parse("module Main where\nanswer = f 42");
+        let json: serde_json::Value = serde_json::from_str(&dump_ast(&module)).unwrap();
+        assert_eq!(json["name"], "Main");
+        assert_eq!(json["members"][0]["name"], "answer");
+        assert_eq!(
+            json["members"][0]["def_expr"],
+            serde_json::json!({
+                "App": [
+                    { "Var": ["f", { "start": 27, "end": 28 }] },
+                    { "IntLit": [42, { "start": 29, "end": 31 }] }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn module_is_named_by_its_header() {
+        let (module, diagnostics) = module("module Data.List where\n\nempty : List\nempty = Nil");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(module.name, "Data.List");
+        assert_eq!(module.members.len(), 1);
+    }
+
+    #[test]
+    fn imports_are_collected() {
+        let (module, _) = module("module Main where\nimport Foo\nimport Bar (baz, qux)\n\nmain = baz");
+        let imported: Vec<_> = module.imports.iter().map(|i| i.name.value.as_str()).collect();
+        assert_eq!(imported, vec!["Foo", "Bar"]);
+        assert!(matches!(module.imports[1].reference, ModuleReference::Many(ref names) if names.len() == 2));
+    }
+
+    #[test]
+    fn fixities_are_collected() {
+        let (module, diagnostics) = module("infixr 5 ++\ninfixl 6 <+>\n");
+        assert_eq!(diagnostics, vec![]);
+        let operators: Vec<_> = module.fixities.iter().map(|f| f.operator.as_str()).collect();
+        assert_eq!(operators, vec!["++", "<+>"]);
+    }
+
+    #[test]
+    fn module_without_header_is_main() {
+        let (module, _) = module("answer : Int\nanswer = 42");
+        assert_eq!(module.name, "Main");
+    }
+
+    #[test]
+    fn late_module_header_is_an_error() {
+        let (module, diagnostics) = module("zero : Int\nzero = 0\nmodule Late where");
+        assert_eq!(module.name, "Main");
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(27..31, "the module header must come before everything else")]
+        );
+    }
+
+    #[test]
+    fn signature_is_paired_with_its_definition() {
+        let (module, diagnostics) = module("answer : Int\nanswer = 42");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(
+            module.members,
+            vec![Spanned::new(
+                Definition {
+                    name: "answer".to_string(),
+                    name_span: 13..19,
+                    def_type: Some(Expr::Var("Int".to_string(), 9..12)),
+                    def_expr: Expr::IntLit(42, 22..24),
+                },
+                13..24
+            )]
+        );
+    }
+
+    #[test]
+    fn unmatched_signatures_and_definitions_are_reported() {
+        let (module, diagnostics) = module("answer : Int\nzero = 0");
+        assert_eq!(module.members.len(), 1);
+        assert_eq!(module.members[0].def_type, None);
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning(13..17, "`zero` has no type signature"),
+                Diagnostic::error(0..6, "`answer` has a type signature but no definition"),
+            ]
+        );
+    }
+}
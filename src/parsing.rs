@@ -1,17 +1,24 @@
 use nom::{
-    branch::alt, bytes::complete::{tag, take_while, is_not}, character::complete::space1, sequence::delimited, IResult
+    branch::alt, bytes::complete::{tag, take, take_until, take_while, take_while1},
+    character::complete::{char, line_ending, none_of, not_line_ending, one_of, satisfy},
+    combinator::{map_res, not, opt, recognize, value, verify}, error::{Error, ErrorKind}, multi::{fold_many0, many0, many1_count},
+    sequence::{delimited, pair, preceded, terminated, tuple}, IResult, Slice
 };
 use nom_locate::{position, LocatedSpan};
+use rayon::prelude::*;
+use crate::package::{Definition, Import, ImportedName, ModuleName, ModuleReference};
+use crate::position::offset_to_position;
+use std::ops::Range;
 
 type Span<'doc> = LocatedSpan<&'doc str>;
 
-struct Tokens<'doc> {
+pub struct Tokens<'doc> {
     pub offset: usize,
     pub tokens: Vec<Token<'doc>>
 }
 
 impl <'doc> Tokens<'doc> {
-    fn new(offset: usize, tokens: Vec<Token<'doc>>) -> Tokens<'doc> {
+    pub fn new(offset: usize, tokens: Vec<Token<'doc>>) -> Tokens<'doc> {
         Tokens {
             offset, 
             tokens
@@ -19,225 +26,3155 @@ impl <'doc> Tokens<'doc> {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Token<'doc> {
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub position: Span<'doc>,
+    /// Byte offset of the token's first character
+    pub start: usize,
+    /// Byte offset just past the token's last character
+    pub end: usize,
     pub content: TokenContent<'doc>
 }
 
 impl <'doc> Token<'doc> {
-    fn new(p: Span<'doc>, c: TokenContent<'doc>) -> Token<'doc> {
+    fn new(p: Span<'doc>, rest: Span<'doc>, c: TokenContent<'doc>) -> Token<'doc> {
         Token {
             position: p,
+            start: p.location_offset(),
+            end: rest.location_offset(),
             content: c
         }
     }
+
+    /// Where the token sits in `doc` by line and character. A token spanning
+    /// several lines ends on the last of them.
+    pub fn lsp_range(&self, doc: &str) -> tower_lsp::lsp_types::Range {
+        tower_lsp::lsp_types::Range::new(offset_to_position(doc, self.start), offset_to_position(doc, self.end))
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TokenContent<'doc> {
     Module,
     Where,
+    Import,
+    Let,
+    In,
+    If,
+    Then,
+    Else,
+    Case,
+    Of,
+    Infixl,
+    Infixr,
+    Infix,
     Equals,
-    String(&'doc str),
+    Arrow,
+    FatArrow,
+    Colon,
+    DoubleColon,
+    Comma,
+    Backslash,
+    /// A lone `_`, matching anything in a pattern
+    Underscore,
+    Dot,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Integer(i64),
+    Float(f64),
+    Char(char),
+    String(String),
+    MultilineString(String),
     Space(usize),
-    Symbol(&'doc str),
+    /// A run of tabs, kept apart from spaces since how wide they are is up
+    /// to the editor
+    Tab(usize),
+    Newline,
+    Operator(&'doc str),
+    Ident(&'doc str),
+    Constructor(&'doc str),
+    QualifiedName(Vec<&'doc str>),
+    LineComment(&'doc str),
+    BlockComment(&'doc str),
+    /// Takes up no space, marking the end of the document
+    Eof,
+    /// A character that couldn't be lexed, kept by `lex_line_resilient` so
+    /// the tokens after it still line up with the source
+    Error(char),
+}
+
+impl TokenContent<'_> {
+    /// Whether this is spacing or a comment, which the grammar skips over
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self,
+            TokenContent::Space(_)
+                | TokenContent::Tab(_)
+                | TokenContent::Newline
+                | TokenContent::LineComment(_)
+                | TokenContent::BlockComment(_)
+        )
+    }
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum ParseError {
     Wrong,
+    UnexpectedChar { offset: usize, found: char },
+    /// The token of length `len` at `offset` isn't what the parser wanted;
+    /// `len` is zero at the end of input
+    Expected { offset: usize, len: usize, expected: String },
+}
+
+impl ParseError {
+    /// Byte offset into the document where the error was found, if known
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            ParseError::Wrong => None,
+            ParseError::UnexpectedChar { offset, .. } | ParseError::Expected { offset, .. } => Some(*offset),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Wrong => write!(f, "couldn't parse this"),
+            ParseError::UnexpectedChar { found, .. } => write!(f, "unexpected character `{}`", found),
+            ParseError::Expected { expected, .. } => write!(f, "expected {}", expected),
+        }
+    }
+}
+
+fn unexpected_char(at: Span) -> ParseError {
+    ParseError::UnexpectedChar {
+        offset: at.location_offset(),
+        found: at.fragment().chars().next().unwrap_or_default(),
+    }
+}
+
+/// A reserved word, as long as it isn't just the start of a longer name
+fn keyword<'doc>(word: &'static str) -> impl FnMut(Span<'doc>) -> IResult<Span<'doc>, Span<'doc>> {
+    terminated(tag(word), not(satisfy(|c: char| c.is_alphanumeric())))
 }
 
 fn lex_module(s: Span) -> IResult<Span, Token> {
-    let (s, _) = tag("module")(s)?;
     let (s, pos) = position(s)?;
-    Ok((s, Token::new(pos, TokenContent::Module)))
+    let (s, _) = keyword("module")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Module)))
 }
 
 fn lex_where(s: Span) -> IResult<Span, Token> {
-    let (s, _) = tag("where")(s)?;
-    let (s, pos) = position(s)?; 
-    Ok((s, Token::new(pos, TokenContent::Where)))
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("where")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Where)))
 }
 
-fn lex_equals(input: Span) -> IResult<Span, Token> {
-    let (s, _) = tag("=")(input)?;
+fn lex_import(s: Span) -> IResult<Span, Token> {
     let (s, pos) = position(s)?;
-    Ok((s, Token::new(pos, TokenContent::Equals)))
+    let (s, _) = keyword("import")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Import)))
 }
 
-fn lex_space(input: Span) -> IResult<Span, Token> {
-    let (s, spaces) = space1(input)?;
+fn lex_let(s: Span) -> IResult<Span, Token> {
     let (s, pos) = position(s)?;
-    Ok((s, Token::new(pos, TokenContent::Space(spaces.len()))))
+    let (s, _) = keyword("let")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Let)))
 }
 
-fn lex_reserved_name(s: Span) -> IResult<Span, Token> {
-    alt((lex_module, lex_where))(s)
+fn lex_in(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("in")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::In)))
 }
 
-fn lex_symbol<'doc>(s: Span<'doc>) -> IResult<Span<'doc>, Token<'doc>> {
-    let (s, sym) = take_while(|c: char| c.is_alphanumeric())(s)?;
+fn lex_if(s: Span) -> IResult<Span, Token> {
     let (s, pos) = position(s)?;
-    Ok((s, Token::new(pos, TokenContent::Symbol(&sym))))
+    let (s, _) = keyword("if")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::If)))
 }
 
-fn lexer<'doc>(input: LocatedSpan<&'doc str>) -> IResult<Span<'doc>, Token<'doc>> {
-    alt((lex_space, lex_single_line_string, lex_reserved_name, lex_equals, lex_symbol))(input)
-} 
+fn lex_then(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("then")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Then)))
+}
+
+fn lex_else(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("else")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Else)))
+}
 
-pub fn lex_single_line_string<'doc>(input: Span<'doc>) -> IResult<Span<'doc>, Token<'doc>> {
-    let (s, str) = delimited(tag("\""), is_not("\""), tag("\""))(input)?;
+fn lex_case(s: Span) -> IResult<Span, Token> {
     let (s, pos) = position(s)?;
-    Ok((s, Token::new(pos, TokenContent::String(&str))))
+    let (s, _) = keyword("case")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Case)))
 }
 
-pub fn lex_line(input: &str) -> Result<Vec<Token>, ParseError> {
-    let mut rest: Span = Span::new(input);
-    let mut tokens: Vec<Token> = vec![];
+fn lex_of(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("of")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Of)))
+}
 
-    loop {
-        if rest.is_empty() {
-            return Ok(tokens);
+fn lex_infixl(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("infixl")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Infixl)))
+}
+
+fn lex_infixr(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("infixr")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Infixr)))
+}
+
+fn lex_infix(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, _) = keyword("infix")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Infix)))
+}
+
+fn is_operator_char(c: char) -> bool {
+    "+-*/<>|&^%=".contains(c)
+}
+
+/// Operators with their own token, so long as they aren't just the start
+/// of a longer user-defined operator
+fn reserved_operator<'doc>(op: &'static str) -> impl FnMut(Span<'doc>) -> IResult<Span<'doc>, Span<'doc>> {
+    terminated(tag(op), not(satisfy(is_operator_char)))
+}
+
+/// Lexes the longest run of operator characters, so `>>=` is one operator
+fn lex_operator(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, op) = take_while1(is_operator_char)(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Operator(op.fragment()))))
+}
+
+fn lex_arrow(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = reserved_operator("->")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Arrow)))
+}
+
+fn lex_fat_arrow(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = reserved_operator("=>")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::FatArrow)))
+}
+
+fn lex_double_colon(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("::")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::DoubleColon)))
+}
+
+fn lex_colon(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag(":")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Colon)))
+}
+
+fn lex_comma(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag(",")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Comma)))
+}
+
+fn lex_backslash(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("\\")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Backslash)))
+}
+
+fn lex_underscore(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = keyword("_")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Underscore)))
+}
+
+fn lex_equals(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = reserved_operator("=")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Equals)))
+}
+
+fn lex_lparen(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("(")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::LParen)))
+}
+
+fn lex_rparen(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag(")")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::RParen)))
+}
+
+fn lex_lbracket(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("[")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::LBracket)))
+}
+
+fn lex_rbracket(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("]")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::RBracket)))
+}
+
+fn lex_lbrace(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("{")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::LBrace)))
+}
+
+fn lex_rbrace(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("}")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::RBrace)))
+}
+
+fn lex_delimiter(s: Span) -> IResult<Span, Token> {
+    alt((lex_lparen, lex_rparen, lex_lbracket, lex_rbracket, lex_lbrace, lex_rbrace))(s)
+}
+
+fn lex_space(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    // A `\r` outside of a `\r\n` line ending is just more space
+    let stray_return = terminated(tag("\r"), not(char('\n')));
+    let (s, spaces) = recognize(many1_count(alt((take_while1(|c| c == ' '), stray_return))))(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Space(spaces.len()))))
+}
+
+fn lex_tabs(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, tabs) = take_while1(|c| c == '\t')(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Tab(tabs.len()))))
+}
+
+fn lex_newline(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = line_ending(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Newline)))
+}
+
+/// How many columns a tab advances to when the editor doesn't say
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// The column the first non-space token of `line` starts at, with tabs
+/// moving to the next multiple of `tab_width`
+pub fn indentation(line: &[Token], tab_width: usize) -> usize {
+    let mut column = 0;
+    for token in line {
+        match token.content {
+            TokenContent::Space(n) => column += n,
+            TokenContent::Tab(n) => column = (column / tab_width + n) * tab_width,
+            _ => break,
         }
-        match lexer(rest) {
-            Ok((more, token)) => {
-                rest = more;
-                tokens.insert(0, token);
+    }
+    column
+}
+
+/// The column `token` starts at, counting the characters before it on its
+/// line the way `indentation` does
+fn column_of(token: &Token, tab_width: usize) -> usize {
+    let line = token.position.get_line_beginning();
+    let before = String::from_utf8_lossy(&line[..token.position.get_column() - 1]);
+    before.chars().fold(0, |column, c| match c {
+        '\t' => (column / tab_width + 1) * tab_width,
+        _ => column + 1,
+    })
+}
+
+fn lex_reserved_name(s: Span) -> IResult<Span, Token> {
+    alt((
+        lex_module, lex_where, lex_import, lex_let, lex_in, lex_if, lex_then, lex_else, lex_case, lex_of, lex_infixl,
+        lex_infixr, lex_infix,
+    ))(s)
+}
+
+fn lex_integer(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, n) = alt((
+        map_res(recognize(tuple((opt(minus), tag("0x"), separated(|c| c.is_ascii_hexdigit())))), |d: Span| {
+            let digits = without_separators(d.fragment());
+            match digits.strip_prefix('-') {
+                Some(positive) => i64::from_str_radix(&positive[2..], 16).map(|n| -n),
+                None => i64::from_str_radix(&digits[2..], 16),
             }
-            Err(_) => return Err(ParseError::Wrong),
+        }),
+        map_res(signed_digits, |d: Span| without_separators(d.fragment()).parse::<i64>()),
+    ))(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Integer(n))))
+}
+
+/// Digits accepted by `digit` with single underscores between them, like
+/// `1_000_000`. An underscore at either end or doubled up isn't a separator,
+/// so the number doesn't lex at all.
+fn separated<'doc>(digit: fn(char) -> bool) -> impl FnMut(Span<'doc>) -> IResult<Span<'doc>, Span<'doc>> {
+    terminated(
+        recognize(pair(take_while1(digit), many0(preceded(char('_'), take_while1(digit))))),
+        not(char('_')),
+    )
+}
+
+fn without_separators(literal: &str) -> String {
+    literal.replace('_', "")
+}
+
+fn decimal(s: Span) -> IResult<Span, Span> {
+    separated(|c| c.is_ascii_digit())(s)
+}
+
+fn exponent(s: Span) -> IResult<Span, Span> {
+    recognize(tuple((one_of("eE"), opt(one_of("+-")), decimal)))(s)
+}
+
+/// Digits, with a leading `-` when it's directly attached. `a - b` with
+/// spaces is subtraction rather than a negative literal.
+fn signed_digits(s: Span) -> IResult<Span, Span> {
+    recognize(pair(opt(minus), decimal))(s)
+}
+
+/// The `-` of a negative literal. Straight after something that ends an
+/// operand it's subtraction instead, so `a-1` and `3-2` aren't applications.
+fn minus(s: Span) -> IResult<Span, Span> {
+    verify(tag("-"), |sign: &Span| {
+        let before = &sign.get_line_beginning()[..sign.get_column() - 1];
+        match std::str::from_utf8(before).ok().and_then(|before| before.chars().next_back()) {
+            None => true,
+            Some(c) => c.is_whitespace() || is_operator_char(c) || "([,".contains(c),
         }
+    })(s)
+}
+
+/// Lexes `3.14`, `1.0e10`, or `1e10`. A trailing dot with no fractional
+/// digits (`3.`) isn't a float, so it's left for `lex_integer`.
+fn lex_float(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, n) = map_res(
+        alt((
+            recognize(tuple((signed_digits, tag("."), decimal, opt(exponent)))),
+            recognize(pair(signed_digits, exponent)),
+        )),
+        |f: Span| without_separators(f.fragment()).parse::<f64>(),
+    )(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Float(n))))
+}
+
+/// A name starting with a letter whose case is accepted by `first`
+fn name<'doc>(first: fn(char) -> bool) -> impl FnMut(Span<'doc>) -> IResult<Span<'doc>, Span<'doc>> {
+    recognize(pair(satisfy(first), take_while(|c: char| c.is_alphanumeric())))
+}
+
+fn lex_ident(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, ident) = name(|c: char| c.is_alphabetic() && !c.is_uppercase())(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Ident(ident.fragment()))))
+}
+
+fn lex_constructor(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, cons) = name(char::is_uppercase)(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::Constructor(cons.fragment()))))
+}
+
+/// Lexes a dotted name like `Data.List` or `Data.List.map`. Every segment
+/// but the last must be capitalized, and a lone name isn't qualified, so at
+/// least one dot is required. A trailing dot (`Foo.`) is left for `lex_dot`.
+fn lex_qualified_name(s: Span) -> IResult<Span, Token> {
+    let (s, pos) = position(s)?;
+    let (s, first) = name(char::is_uppercase)(s)?;
+    let (s, modules) = many0(preceded(char('.'), name(char::is_uppercase)))(s)?;
+    let (s, member) = opt(preceded(char('.'), name(|c: char| c.is_alphabetic() && !c.is_uppercase())))(s)?;
+    if modules.is_empty() && member.is_none() {
+        return Err(nom::Err::Error(Error::new(s, ErrorKind::Many1)));
     }
+    let segments = std::iter::once(first)
+        .chain(modules)
+        .chain(member)
+        .map(|n| *n.fragment())
+        .collect();
+    Ok((s, Token::new(pos, s, TokenContent::QualifiedName(segments))))
 }
 
-enum Partial {
-    Empty
+fn lex_dot(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag(".")(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Dot)))
 }
 
-enum PartialExpr {
-    Partial(Option<Partial>, Option<Partial>, Option<Partial>),
-    Empty
+fn lex_symbol(s: Span) -> IResult<Span, Token> {
+    alt((lex_ident, lex_constructor))(s)
 }
 
-pub fn parse_partial<'doc>(input: &Tokens<'doc>) -> Result<PartialExpr, ParseError> {
-    Ok(PartialExpr::Empty)
+fn lex_line_comment(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("--")(input)?;
+    let (s, comment) = not_line_ending(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::LineComment(comment.fragment()))))
 }
 
-pub fn combine_parts(
-    left: Result<PartialExpr, Vec<ParseError>>, 
-    right: Result<&PartialExpr, Vec<ParseError>>
-) -> Result<PartialExpr, Vec<ParseError>> {
-    match left {
-        Ok(l) => match right {
-            Ok(_) => Ok(l),
-            Err(re) => Err(re), 
-        },
-        Err(le) => match right {
-            Err(re) => Err(vec![le, re].concat()), 
-            Ok(_) => Err(le) 
+/// Lexes a `{- ... -}` comment, allowing nested block comments inside.
+/// The comment may run over several lines.
+fn lex_block_comment(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("{-")(input)?;
+    let body = s.fragment();
+    let mut depth = 1;
+    let mut i = 0;
+    while depth > 0 {
+        let rest = &body[i..];
+        if rest.is_empty() {
+            // Unterminated, don't let another lexer have a go at it
+            return Err(nom::Err::Failure(Error::new(input, ErrorKind::TakeUntil)));
+        } else if rest.starts_with("{-") {
+            depth += 1;
+            i += 2;
+        } else if rest.starts_with("-}") {
+            depth -= 1;
+            i += 2;
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
         }
     }
+    let (s, comment) = take(i - 2)(s)?;
+    let (s, _) = tag("-}")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::BlockComment(comment.fragment()))))
+}
+
+fn lexer<'doc>(input: LocatedSpan<&'doc str>) -> IResult<Span<'doc>, Token<'doc>> {
+    alt((lex_space, lex_tabs, lex_newline, lex_comment, lex_literal, lex_reserved_name, lex_punctuation, lex_operator, lex_qualified_name, lex_symbol, lex_dot))(input)
+}
+
+fn lex_comment(s: Span) -> IResult<Span, Token> {
+    alt((lex_line_comment, lex_block_comment))(s)
 }
 
-enum Expr {
+/// Floats come before integers so `3.14` isn't split at the dot
+fn lex_literal(s: Span) -> IResult<Span, Token> {
+    alt((lex_char, lex_multiline_string, lex_single_line_string, lex_float, lex_integer))(s)
+}
 
+fn lex_punctuation(s: Span) -> IResult<Span, Token> {
+    alt((lex_arrow, lex_fat_arrow, lex_double_colon, lex_colon, lex_comma, lex_backslash, lex_underscore, lex_equals, lex_delimiter))(s)
 }
 
-pub fn complete_expression(part: PartialExpr) -> Result<Expr, ParseError> {
-    Err(ParseError::Wrong)
+/// An escape sequence like `\n` or `\'`, shared by character and string literals
+fn escape(s: Span) -> IResult<Span, char> {
+    preceded(
+        char('\\'),
+        alt((
+            value('\n', char('n')),
+            value('\t', char('t')),
+            value('\r', char('r')),
+            value('\0', char('0')),
+            value('\\', char('\\')),
+            value('\'', char('\'')),
+            value('"', char('"')),
+        )),
+    )(s)
 }
 
-pub fn parse_expr<'doc>(input: &'doc str) -> Result<Expr, ParseError> {
-    // split string into lines
-    let lines = input.lines();
-    let mut partials = vec![];
+fn lex_char(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, c) = delimited(char('\''), alt((escape, none_of("\\'\n"))), char('\''))(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::Char(c))))
+}
 
-    // tokenize lines
-    // TODO: Parallelize
-    for line in lines {
-        match lex_line(line) {
-            Ok(line_tokens) => {
-                let tokens = Tokens::new(0, line_tokens);
-                // parse line into partial expression
-                match parse_partial(&tokens) {
-                    Ok(part) => partials.push(part),
-                    Err(_) => ()
-                }
-            },
-            Err(_) => ()
+pub fn lex_single_line_string(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, str) = delimited(
+        char('"'),
+        fold_many0(alt((escape, none_of("\\\"\n"))), String::new, |mut acc, c| {
+            acc.push(c);
+            acc
+        }),
+        char('"'),
+    )(input)?;
+    Ok((s, Token::new(pos, s, TokenContent::String(str))))
+}
+
+/// Lexes a `"""..."""` string, keeping everything between the quotes
+/// verbatim, newlines included.
+pub fn lex_multiline_string(input: Span) -> IResult<Span, Token> {
+    let (input, pos) = position(input)?;
+    let (s, _) = tag("\"\"\"")(input)?;
+    let (s, str) = take_until("\"\"\"")(s)
+        .map_err(|_: nom::Err<Error<Span>>| nom::Err::Failure(Error::new(input, ErrorKind::TakeUntil)))?;
+    let (s, _) = tag("\"\"\"")(s)?;
+    Ok((s, Token::new(pos, s, TokenContent::MultilineString(str.fragment().to_string()))))
+}
+
+/// The byte order mark some editors start a file with. It isn't part of the
+/// source, so lexing starts after it, though offsets are still counted from
+/// the very start of the document.
+const BYTE_ORDER_MARK: &str = "\u{feff}";
+
+/// Where the source of `input` starts, after any byte order mark
+fn source_start(input: &str) -> usize {
+    if input.starts_with(BYTE_ORDER_MARK) {
+        BYTE_ORDER_MARK.len()
+    } else {
+        0
+    }
+}
+
+/// The `Eof` token at the end of `input`
+fn end_of(input: &str) -> Token<'_> {
+    let end = Span::new(input).slice(input.len()..);
+    Token::new(end, end, TokenContent::Eof)
+}
+
+/// Lexes the whole of `input`, ending with an `Eof` token
+pub fn lex_line(input: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut tokens = lex_iter(input).collect::<Result<Vec<_>, _>>()?;
+    tokens.push(end_of(input));
+    Ok(tokens)
+}
+
+/// Lexes the whole of `input` like `lex_line`, but carries on past
+/// characters that can't be lexed. Each one becomes an `Error` token and an
+/// error of its own, so every problem is reported at once.
+pub fn lex_line_resilient(input: &str) -> (Vec<Token<'_>>, Vec<ParseError>) {
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    let mut lexer = lex_iter(input);
+    loop {
+        for token in lexer.by_ref() {
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
         }
+        if !lexer.failed {
+            break;
+        }
+        // the lexer stopped without moving past the character it choked on
+        let bad = lexer.rest;
+        let found = bad.fragment().chars().next().unwrap_or_default();
+        let after = bad.slice(found.len_utf8()..);
+        tokens.push(Token::new(bad, after, TokenContent::Error(found)));
+        lexer = Lexer::new(after);
+    }
+    tokens.push(end_of(input));
+    (tokens, errors)
+}
+
+/// Lexes `input` one token at a time, so a caller that only needs the start
+/// of a document doesn't pay for the rest of it
+pub fn lex_iter(input: &str) -> Lexer<'_> {
+    Lexer::new(Span::new(input).slice(source_start(input)..))
+}
+
+/// An iterator over the tokens of a document. It stops after yielding the
+/// first error.
+pub struct Lexer<'doc> {
+    rest: Span<'doc>,
+    failed: bool,
+}
+
+impl<'doc> Lexer<'doc> {
+    fn new(input: Span<'doc>) -> Lexer<'doc> {
+        Lexer { rest: input, failed: false }
     }
 
-    // try to combine all partial expressions 
-    // TODO: Parallelize
-    match partials.iter().map(Ok).fold(Ok(PartialExpr::Empty), combine_parts) {
-        Ok(result) => complete_expression(result),
-        Err(_) => Err(ParseError::Wrong)
+    /// Byte offset of the first character not yet lexed
+    pub fn offset(&self) -> usize {
+        self.rest.location_offset()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl<'doc> Iterator for Lexer<'doc> {
+    type Item = Result<Token<'doc>, ParseError>;
 
-    fn is_token_content(input: &str, given: TokenContent) { 
-        match lex_line(input) {
-            Ok(tokens) => {
-                assert_eq!(tokens.len(), 1);
-                let t = &tokens[0];
-                assert_eq!(t.content, given);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.rest.is_empty() {
+            return None;
+        }
+        match lexer(self.rest) {
+            // A lexer matching nothing would leave us spinning here forever
+            Ok((more, token)) if more.location_offset() != self.rest.location_offset() => {
+                self.rest = more;
+                Some(Ok(token))
+            }
+            _ => {
+                self.failed = true;
+                Some(Err(unexpected_char(self.rest)))
             }
-            Err(e) => panic!("Unexpected lexing error! {:?}", e),
         }
     }
+}
 
-    #[test]
-    fn can_lex_module() {
-        is_token_content("module", TokenContent::Module);
+/// Lexes each line of `input` in parallel. Block comments and multiline
+/// strings can span lines, so a line holding the start of one fails to lex on
+/// its own; when any line fails the document is lexed serially instead, which
+/// also reports the error at its exact position.
+pub fn lex_lines(input: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut lines = vec![];
+    let mut rest = Span::new(input).slice(source_start(input)..);
+    while !rest.is_empty() {
+        let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        lines.push(rest.slice(..len));
+        rest = rest.slice(len..);
     }
+    let lexed: Result<Vec<Vec<Token>>, ParseError> = lines.into_par_iter().map(lex_span).collect();
+    match lexed {
+        Ok(lines) => Ok(lines.into_iter().flatten().collect()),
+        Err(_) => lex_iter(input).collect(),
+    }
+}
 
-    #[test]
-    fn can_lex_where() {
-        is_token_content("where", TokenContent::Where);
+/// Lexes a whole document, tagging each token with the zero-based line it
+/// starts on. The last token is an `Eof`.
+pub fn lex_all(text: &str) -> Result<Vec<(usize, Token<'_>)>, ParseError> {
+    let mut tokens = lex_lines(text)?;
+    tokens.push(end_of(text));
+    Ok(tokens.into_iter().map(|t| (t.position.location_line() as usize - 1, t)).collect())
+}
+
+fn lex_span(input: Span) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(input).collect()
+}
+
+/// The tokens lexed before the first error, if there is one
+fn lex_prefix(input: Span) -> (Vec<Token>, Option<ParseError>) {
+    let mut tokens = vec![];
+    for token in Lexer::new(input) {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(error) => return (tokens, Some(error)),
+        }
     }
+    (tokens, None)
+}
 
-    #[test]
-    fn can_lex_equals() {
-        is_token_content("=", TokenContent::Equals);
+fn starts_declaration(line: &str) -> bool {
+    line.starts_with(|c: char| !c.is_whitespace())
+}
+
+/// Lexes as much of `input` as possible. When a character can't be lexed,
+/// the top-level declaration holding it is dropped and lexing picks up again
+/// at the next unindented line.
+pub(crate) fn lex_recovering(input: &str) -> (Vec<Token<'_>>, Vec<ParseError>) {
+    if let Ok(tokens) = lex_lines(input) {
+        return (tokens, vec![]);
     }
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    let mut rest = Span::new(input).slice(source_start(input)..);
+    while !rest.is_empty() {
+        let start = rest.location_offset();
+        let (lexed, error) = lex_prefix(rest);
+        let Some(error) = error else {
+            tokens.extend(lexed);
+            break;
+        };
+        let at = error.offset().unwrap_or(start);
 
-    #[test]
-    fn can_lex_space() {
-        match lex_line(" ") {
-            Ok(tokens) => {
-                assert_eq!(tokens.len(), 1);
-                let t = &tokens[0];
-                assert_eq!(t.content, TokenContent::Space(1))
-            },
-            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        // the unindented line the broken declaration starts on
+        let mut declaration = input[..at].rfind('\n').map_or(0, |i| i + 1);
+        while declaration > start && !starts_declaration(&input[declaration..]) {
+            declaration = input[..declaration - 1].rfind('\n').map_or(0, |i| i + 1);
+        }
+        tokens.extend(lexed.into_iter().filter(|t| t.end <= declaration));
+        errors.push(error);
+
+        // the next unindented line after the error, moving the span on so
+        // only the lines skipped over are counted
+        let mut resume = input.len();
+        let mut next = at;
+        while let Some(i) = input[next..].find('\n') {
+            next += i + 1;
+            if starts_declaration(&input[next..]) {
+                resume = next;
+                break;
+            }
         }
+        rest = rest.slice(resume - start..);
     }
+    (tokens, errors)
+}
 
-    #[test]
-    fn spaces_have_correct_length() {
-        match lex_line("    ") {
-            Ok(tokens) => {
-                assert_eq!(tokens.len(), 1);
-                let t = &tokens[0];
-                assert_eq!(t.content, TokenContent::Space(4))
-            },
-            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+/// Top-level declarations gathered a line at a time, before they're parsed
+#[derive(PartialEq, Debug)]
+pub enum PartialExpr<'doc> {
+    /// A line with nothing to parse on it, like a blank or comment-only line
+    Empty,
+    /// The lines of each declaration so far; the last declaration can still
+    /// be continued by indented lines
+    Declarations(Vec<Vec<Token<'doc>>>),
+    /// An indented line, continuing the declaration above it
+    Continuation(Vec<Token<'doc>>),
+}
+
+/// Decides what a single line contributes to the declarations around it
+pub fn parse_partial(line: Tokens) -> PartialExpr {
+    let tokens = line.tokens;
+    if !tokens.iter().any(is_significant) {
+        PartialExpr::Empty
+    } else if matches!(tokens[0].content, TokenContent::Space(_) | TokenContent::Tab(_)) {
+        PartialExpr::Continuation(tokens)
+    } else {
+        PartialExpr::Declarations(vec![tokens])
+    }
+}
+
+/// Stitches the lines on the right onto the declarations on the left. An
+/// indented line with no declaration above it starts one of its own.
+pub fn combine_parts<'doc>(left: PartialExpr<'doc>, right: PartialExpr<'doc>) -> PartialExpr<'doc> {
+    match (left, right) {
+        (left, PartialExpr::Empty) => left,
+        (PartialExpr::Empty, PartialExpr::Continuation(tokens)) => PartialExpr::Declarations(vec![tokens]),
+        (PartialExpr::Empty, right) => right,
+        (PartialExpr::Continuation(mut tokens), PartialExpr::Continuation(more)) => {
+            tokens.extend(more);
+            PartialExpr::Declarations(vec![tokens])
+        }
+        (PartialExpr::Continuation(tokens), PartialExpr::Declarations(more)) => {
+            PartialExpr::Declarations([vec![tokens], more].concat())
+        }
+        (PartialExpr::Declarations(mut declarations), PartialExpr::Continuation(tokens)) => {
+            if let Some(last) = declarations.last_mut() {
+                last.extend(tokens);
+            }
+            PartialExpr::Declarations(declarations)
+        }
+        (PartialExpr::Declarations(mut declarations), PartialExpr::Declarations(more)) => {
+            declarations.extend(more);
+            PartialExpr::Declarations(declarations)
         }
     }
+}
 
-    #[test]
-    fn can_lex_symbol() {
-        is_token_content("hello", TokenContent::Symbol("hello"));
+/// Parses each gathered declaration, keeping the ones that parse and the
+/// reasons the others didn't. Fixity declarations are read first, so an
+/// operator can be used above the line giving its fixity. Layout is read
+/// with tabs `tab_width` columns wide.
+pub fn complete_declarations(part: PartialExpr, tab_width: usize) -> (Vec<TopLevel>, Vec<ParseError>) {
+    let lines = match part {
+        PartialExpr::Empty => vec![],
+        PartialExpr::Declarations(lines) => lines,
+        PartialExpr::Continuation(tokens) => vec![tokens],
+    };
+    let fixities: Vec<Fixity> = lines
+        .iter()
+        .filter(|tokens| {
+            let first = tokens.iter().find(|t| is_significant(t)).map(|t| &t.content);
+            matches!(first, Some(TokenContent::Infixl | TokenContent::Infixr | TokenContent::Infix))
+        })
+        .filter_map(|tokens| Parser::with_tab_width(tokens, tab_width).fixity().ok())
+        .collect();
+    let mut declarations = vec![];
+    let mut errors = vec![];
+    for tokens in &lines {
+        let mut parser = Parser::with_tab_width(tokens, tab_width).with_fixities(fixities.clone());
+        match parser.top_level() {
+            Ok(_) if !parser.at_end() => errors.push(parser.expected("the end of the declaration")),
+            Ok(declaration) => declarations.push(declaration),
+            Err(error) => errors.push(error),
+        }
+    }
+    (declarations, errors)
+}
+
+/// An expression, with every node carrying the byte range of source it was
+/// parsed from
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Expr {
+    IntLit(i64, Range<usize>),
+    FloatLit(f64, Range<usize>),
+    StringLit(String, Range<usize>),
+    Var(String, Range<usize>),
+    /// `[a, b, c]`, spanning the brackets. A trailing comma before the `]`
+    /// is allowed, so a list split over lines can end every element with one.
+    List(Vec<Expr>, Range<usize>),
+    /// `(a, b)` with two or more elements, or the unit `()` with none,
+    /// spanning the parentheses. `(a)` is just `a`.
+    Tuple(Vec<Expr>, Range<usize>),
+    /// A function applied to a single argument; spans from the start of the
+    /// function to the end of the argument
+    App(Box<Expr>, Box<Expr>),
+    /// An infix operator applied to both sides
+    BinOp(String, Box<Expr>, Box<Expr>),
+    /// A function of one parameter; `\x y -> body` is a lambda of `x`
+    /// returning a lambda of `y`, whose `start` is `y`'s
+    Lam { param: String, param_span: Range<usize>, body: Box<Expr>, start: usize },
+    /// `let name = bound in body`; a `let` with several bindings nests one
+    /// `Let` per binding, each inner one starting at its name
+    Let { name: String, name_span: Range<usize>, bound: Box<Expr>, body: Box<Expr>, start: usize },
+    /// `if cond then a else b`, starting at the `if`
+    If { cond: Box<Expr>, then_branch: Box<Expr>, else_branch: Box<Expr>, start: usize },
+    /// `case scrutinee of` followed by one `pattern -> body` arm per line,
+    /// starting at the `case`
+    Case { scrutinee: Box<Expr>, arms: Vec<(Pattern, Expr)>, start: usize },
+    /// The body of a definition followed by `where` and the definitions
+    /// local to it, which can all see each other. It sits inside the
+    /// lambdas of the definition's parameters, so the bindings see those too.
+    Where { body: Box<Expr>, bindings: Vec<Spanned<Definition>> },
+}
+
+impl Expr {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Expr::IntLit(_, span) | Expr::FloatLit(_, span) | Expr::StringLit(_, span) | Expr::Var(_, span) => {
+                span.clone()
+            }
+            Expr::List(_, span) | Expr::Tuple(_, span) => span.clone(),
+            Expr::App(func, arg) => func.span().start..arg.span().end,
+            Expr::BinOp(_, lhs, rhs) => lhs.span().start..rhs.span().end,
+            Expr::Lam { body, start, .. } | Expr::Let { body, start, .. } => *start..body.span().end,
+            Expr::If { else_branch, start, .. } => *start..else_branch.span().end,
+            Expr::Case { scrutinee, arms, start } => {
+                *start..arms.last().map_or(scrutinee.span().end, |(_, body)| body.span().end)
+            }
+            Expr::Where { body, bindings } => {
+                body.span().start..bindings.last().map_or(body.span().end, |binding| binding.end)
+            }
+        }
     }
+}
 
-    #[test]
-    fn can_lex_single_line_string() {
-        is_token_content("\"hello\"", TokenContent::String("hello"));
+/// A node along with the byte range of source it was parsed from, for nodes
+/// that don't carry one of their own. It derefs to the node.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Spanned<T> {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub node: T,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Range<usize>) -> Spanned<T> {
+        Spanned { node, start: span.start, end: span.end }
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> std::ops::DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+/// What a `case` arm matches its scrutinee against
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Pattern {
+    IntLit(i64, Range<usize>),
+    StringLit(String, Range<usize>),
+    /// Matches anything, binding it to the name for the arm's body
+    Var(String, Range<usize>),
+    /// `_`, matching anything without binding it
+    Wildcard(Range<usize>),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Pattern::IntLit(_, span) | Pattern::StringLit(_, span) | Pattern::Var(_, span) | Pattern::Wildcard(span) => {
+                span.clone()
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Associativity {
+    Left,
+    Right,
+    /// Can't be chained without parentheses, like `a == b == c`
+    None,
+}
+
+/// An `infixl 6 +` declaration: how tightly `operator` binds, from 0 to 9,
+/// and which way it groups. `infix` is `Associativity::None`.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Fixity {
+    pub assoc: Associativity,
+    pub precedence: u8,
+    pub operator: String,
+}
+
+/// The fixity of `op`: the last of `declared` for it, or the built-in one
+pub(crate) fn fixity_of(declared: &[Fixity], op: &str) -> (u8, Associativity) {
+    match declared.iter().rev().find(|f| f.operator == op) {
+        Some(fixity) => (fixity.precedence, fixity.assoc),
+        None => builtin_fixity(op),
+    }
+}
+
+/// How tightly a built-in operator binds, from 0 to 9, and which way it
+/// groups. Application binds tighter than any operator, and anything
+/// unknown is `infixl 9`.
+fn builtin_fixity(op: &str) -> (u8, Associativity) {
+    match op {
+        "|>" => (1, Associativity::Left),
+        "<|" => (1, Associativity::Right),
+        "||" => (2, Associativity::Right),
+        "&&" => (3, Associativity::Right),
+        "==" | "<" | "<=" | ">" | ">=" => (4, Associativity::None),
+        "++" => (5, Associativity::Right),
+        "+" | "-" => (6, Associativity::Left),
+        "*" | "/" | "%" => (7, Associativity::Left),
+        "^" => (8, Associativity::Right),
+        _ => (9, Associativity::Left),
+    }
+}
+
+/// A `name : Type` line giving the type of a top-level definition
+#[derive(PartialEq, Clone, Debug)]
+pub struct Signature {
+    pub name: String,
+    pub name_span: Range<usize>,
+    pub ty: Expr,
+}
+
+/// The `module Foo.Bar where` line naming a module
+#[derive(PartialEq, Clone, Debug)]
+pub struct ModuleHeader {
+    pub name: String,
+    pub name_span: Range<usize>,
+    /// The names listed in `module Foo.Bar (bar, baz) where`, if there's a
+    /// list
+    pub exports: Option<Vec<ImportedName>>,
+}
+
+/// Anything that can appear unindented at the top level of a module
+#[derive(PartialEq, Clone, Debug)]
+pub enum TopLevel {
+    Header(ModuleHeader),
+    Import(Import),
+    Signature(Signature),
+    Fixity(Fixity),
+    Definition(Spanned<Definition>),
+}
+
+fn is_significant(token: &Token) -> bool {
+    !token.content.is_trivia() && token.content != TokenContent::Eof
+}
+
+/// The tokens the grammar cares about, each with its index in `tokens` so the
+/// trivia between them can be found again
+pub fn significant_tokens<'a, 'doc>(tokens: &'a [Token<'doc>]) -> Vec<(usize, &'a Token<'doc>)> {
+    tokens.iter().enumerate().filter(|(_, t)| is_significant(t)).collect()
+}
+
+/// A recursive-descent parser over the significant tokens of a document, so
+/// spaces, newlines and comments never need to be handled by the grammar
+pub struct Parser<'a, 'doc> {
+    tokens: Vec<&'a Token<'doc>>,
+    /// For each token, whether it's the first on its line and the column it
+    /// starts at, with tabs expanded
+    layout: Vec<(bool, usize)>,
+    /// The columns of the `case` arms and `where` bindings being parsed,
+    /// innermost last
+    arm_columns: Vec<usize>,
+    /// Fixities declared by the module, used over the built-in ones
+    fixities: Vec<Fixity>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a, 'doc> Parser<'a, 'doc> {
+    /// `tokens` are expected to start at the beginning of a line
+    pub fn new(tokens: &'a [Token<'doc>]) -> Parser<'a, 'doc> {
+        Parser::with_tab_width(tokens, DEFAULT_TAB_WIDTH)
+    }
+
+    /// A parser lining up `case` arms and `where` bindings with tabs
+    /// advancing to the next multiple of `tab_width`
+    pub fn with_tab_width(tokens: &'a [Token<'doc>], tab_width: usize) -> Parser<'a, 'doc> {
+        let mut layout = vec![];
+        let mut starts_line = true;
+        for token in tokens {
+            if token.content == TokenContent::Newline {
+                starts_line = true;
+            } else if is_significant(token) {
+                layout.push((starts_line, column_of(token, tab_width)));
+                starts_line = false;
+            }
+        }
+        let tokens: Vec<_> = tokens.iter().filter(|t| is_significant(t)).collect();
+        // running out of input is reported just after the last real token
+        let end = tokens.last().map_or(0, |t| t.end);
+        Parser { tokens, layout, arm_columns: vec![], fixities: vec![], pos: 0, end }
+    }
+
+    /// Parses operators with the given fixities rather than the built-in ones
+    pub fn with_fixities(mut self, fixities: Vec<Fixity>) -> Parser<'a, 'doc> {
+        self.fixities = fixities;
+        self
+    }
+
+    fn peek(&self) -> Option<&'a Token<'doc>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&'a Token<'doc>> {
+        self.tokens.get(self.pos + n).copied()
+    }
+
+    fn advance(&mut self) -> Option<&'a Token<'doc>> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Runs `parse`, recording the range from its first token to its last
+    fn spanned<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Spanned<T>, ParseError> {
+        let start = self.peek().map_or(self.end, |t| t.start);
+        let node = parse(self)?;
+        let end = match self.pos {
+            0 => start,
+            pos => self.tokens[pos - 1].end.max(start),
+        };
+        Ok(Spanned { node, start, end })
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// An error saying `expected` was wanted instead of the next token
+    fn expected(&self, expected: &str) -> ParseError {
+        let (offset, len) = self.peek().map_or((self.end, 0), |t| (t.start, t.end - t.start));
+        ParseError::Expected { offset, len, expected: expected.to_string() }
+    }
+
+    fn expect(&mut self, content: TokenContent, expected: &str) -> Result<&'a Token<'doc>, ParseError> {
+        match self.peek() {
+            Some(token) if token.content == content => {
+                self.advance();
+                Ok(token)
+            }
+            _ => Err(self.expected(expected)),
+        }
+    }
+
+    /// The name a signature or definition starts with
+    fn declared_name(&mut self) -> Result<(String, Range<usize>), ParseError> {
+        match self.peek() {
+            Some(token @ Token { content: TokenContent::Ident(name), .. }) => {
+                self.advance();
+                Ok((name.to_string(), token.start..token.end))
+            }
+            _ => Err(self.expected("a definition name")),
+        }
+    }
+
+    /// A header, signature or definition; signatures and definitions are
+    /// told apart by what follows the name
+    pub fn top_level(&mut self) -> Result<TopLevel, ParseError> {
+        match self.peek().map(|t| &t.content) {
+            Some(TokenContent::Module) => return self.module_header().map(TopLevel::Header),
+            Some(TokenContent::Import) => return self.import().map(TopLevel::Import),
+            Some(TokenContent::Infixl | TokenContent::Infixr | TokenContent::Infix) => {
+                return self.fixity().map(TopLevel::Fixity)
+            }
+            _ => {}
+        }
+        match self.peek_nth(1).map(|t| &t.content) {
+            Some(TokenContent::Colon) => self.signature().map(TopLevel::Signature),
+            _ => self.definition().map(TopLevel::Definition),
+        }
+    }
+
+    /// A `module Foo.Bar where` header, which may list the names it exports
+    /// as in `module Foo.Bar (bar, baz) where`
+    pub fn module_header(&mut self) -> Result<ModuleHeader, ParseError> {
+        self.expect(TokenContent::Module, "`module`")?;
+        let (name, name_span) = self.module_name()?;
+        let exports = if self.peek().is_some_and(|t| t.content == TokenContent::LParen) {
+            Some(self.name_list("a name to export")?.0)
+        } else {
+            None
+        };
+        self.expect(TokenContent::Where, "`where`")?;
+        Ok(ModuleHeader { name, name_span, exports })
+    }
+
+    /// An `infixl 6 +`, `infixr 5 ++` or `infix 4 ==` declaration
+    pub fn fixity(&mut self) -> Result<Fixity, ParseError> {
+        let assoc = match self.peek().map(|t| &t.content) {
+            Some(TokenContent::Infixl) => Associativity::Left,
+            Some(TokenContent::Infixr) => Associativity::Right,
+            Some(TokenContent::Infix) => Associativity::None,
+            _ => return Err(self.expected("`infixl`, `infixr` or `infix`")),
+        };
+        self.advance();
+        let precedence = match self.peek().map(|t| &t.content) {
+            Some(&TokenContent::Integer(p)) if (0..=9).contains(&p) => p as u8,
+            _ => return Err(self.expected("a precedence from 0 to 9")),
+        };
+        self.advance();
+        let operator = match self.peek().map(|t| &t.content) {
+            Some(TokenContent::Operator(op)) => op.to_string(),
+            _ => return Err(self.expected("an operator")),
+        };
+        self.advance();
+        Ok(Fixity { assoc, precedence, operator })
+    }
+
+    /// A dotted module name where every part is capitalized
+    fn module_name(&mut self) -> Result<(String, Range<usize>), ParseError> {
+        let name = match self.peek().map(|t| &t.content) {
+            Some(TokenContent::Constructor(name)) => name.to_string(),
+            Some(TokenContent::QualifiedName(segments))
+                if segments.iter().all(|s| s.starts_with(|c: char| c.is_uppercase())) =>
+            {
+                segments.join(".")
+            }
+            _ => return Err(self.expected("a module name like `Data.List`")),
+        };
+        let token = self.advance().expect("the name was just peeked");
+        Ok((name, token.start..token.end))
+    }
+
+    /// `import Foo` brings in everything, `import Foo (bar, baz)` only the
+    /// listed names
+    pub fn import(&mut self) -> Result<Import, ParseError> {
+        let start = self.expect(TokenContent::Import, "`import`")?.start;
+        let (value, name_span) = self.module_name()?;
+        let mut end = name_span.end;
+        let reference = if self.peek().is_some_and(|t| t.content == TokenContent::LParen) {
+            let (mut names, list_end) = self.name_list("a name to import")?;
+            end = list_end;
+            if names.len() == 1 {
+                ModuleReference::Single(names.remove(0))
+            } else {
+                ModuleReference::Many(names)
+            }
+        } else {
+            ModuleReference::WildCard
+        };
+        Ok(Import { name: ModuleName { value, span: name_span }, reference, span: start..end })
+    }
+
+    /// A parenthesized list of at least one name, as imports and exports
+    /// have, along with the end of its `)`. `what` describes a name when
+    /// one is missing.
+    fn name_list(&mut self, what: &str) -> Result<(Vec<ImportedName>, usize), ParseError> {
+        self.expect(TokenContent::LParen, "`(`")?;
+        let mut names = vec![self.listed_name(what)?];
+        while self.peek().is_some_and(|t| t.content == TokenContent::Comma) {
+            self.advance();
+            names.push(self.listed_name(what)?);
+        }
+        let end = self.expect(TokenContent::RParen, "`,` or `)`")?.end;
+        Ok((names, end))
+    }
+
+    fn listed_name(&mut self, what: &str) -> Result<ImportedName, ParseError> {
+        match self.peek() {
+            Some(token @ Token { content: TokenContent::Ident(name) | TokenContent::Constructor(name), .. }) => {
+                self.advance();
+                Ok(ImportedName { name: name.to_string(), span: token.start..token.end })
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    /// A top-level `name : Type` signature
+    pub fn signature(&mut self) -> Result<Signature, ParseError> {
+        let (name, name_span) = self.declared_name()?;
+        self.expect(TokenContent::Colon, "`:`")?;
+        let ty = self.expression()?;
+        Ok(Signature { name, name_span, ty })
+    }
+
+    /// A top-level `name = expr` definition, spanning from its name to the
+    /// end of its body
+    pub fn definition(&mut self) -> Result<Spanned<Definition>, ParseError> {
+        self.spanned(Parser::definition_node)
+    }
+
+    fn definition_node(&mut self) -> Result<Definition, ParseError> {
+        let (name, name_span) = self.declared_name()?;
+        let mut params = vec![];
+        while let Some(token @ Token { content: TokenContent::Ident(param), .. }) = self.peek() {
+            self.advance();
+            params.push((param.to_string(), token.start..token.end));
+        }
+        self.expect(TokenContent::Equals, "`=`")?;
+        // `f x y = body` is the same as `f = \x y -> body`
+        let mut def_expr = self.expression()?;
+        if self.peek().is_some_and(|t| t.content == TokenContent::Where) {
+            def_expr = self.where_block(def_expr)?;
+        }
+        for (param, param_span) in params.into_iter().rev() {
+            let start = param_span.start;
+            def_expr = Expr::Lam { param, param_span, body: Box::new(def_expr), start };
+        }
+        Ok(Definition { name, name_span, def_type: None, def_expr })
+    }
+
+    /// `where` after the body of a definition, then the local definitions,
+    /// each starting a line at the column of the first
+    fn where_block(&mut self, body: Expr) -> Result<Expr, ParseError> {
+        self.expect(TokenContent::Where, "`where`")?;
+        let column = self.layout.get(self.pos).map_or(0, |&(_, column)| column);
+        let mut bindings = vec![];
+        loop {
+            // a line back at the column ends the binding above it
+            self.arm_columns.push(column);
+            let binding = self.definition();
+            self.arm_columns.pop();
+            bindings.push(binding?);
+            match self.layout.get(self.pos) {
+                Some(&(true, next)) if next == column => {}
+                _ => break,
+            }
+        }
+        Ok(Expr::Where { body: Box::new(body), bindings })
+    }
+
+    /// Operators applied to applications, grouped by precedence
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.operators(0)
+    }
+
+    /// Precedence climbing: parses operators binding at least as tightly as
+    /// `min_precedence`, leaving looser ones to the caller
+    fn operators(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.application()?;
+        let mut previous: Option<(&str, u8, Associativity)> = None;
+        while let Some(TokenContent::Operator(op)) = self.peek().map(|t| &t.content) {
+            let (precedence, assoc) = fixity_of(&self.fixities, op);
+            if precedence < min_precedence {
+                break;
+            }
+            if let Some((before, p, Associativity::None)) = previous {
+                if p == precedence {
+                    return Err(self.expected(&format!("parentheses to group `{}` with `{}`", before, op)));
+                }
+            }
+            self.advance();
+            let rhs = match assoc {
+                Associativity::Right => self.operators(precedence)?,
+                Associativity::Left | Associativity::None => self.operators(precedence + 1)?,
+            };
+            lhs = Expr::BinOp(op.to_string(), Box::new(lhs), Box::new(rhs));
+            previous = Some((op, precedence, assoc));
+        }
+        Ok(lhs)
+    }
+
+    /// Whether the next tokens are `name =`, which always starts a new
+    /// binding rather than continuing the current expression
+    fn at_binding(&self) -> bool {
+        matches!(self.peek().map(|t| &t.content), Some(TokenContent::Ident(_)))
+            && self.peek_nth(1).is_some_and(|t| t.content == TokenContent::Equals)
+    }
+
+    /// Whether the next token starts a line no further in than the arms of
+    /// the innermost `case` or the bindings of the innermost `where`, which
+    /// ends the current arm or binding
+    fn at_arm_end(&self) -> bool {
+        match (self.layout.get(self.pos), self.arm_columns.last()) {
+            (Some(&(true, column)), Some(&arm_column)) => column <= arm_column,
+            _ => false,
+        }
+    }
+
+    /// Function application: one or more atoms, applied left to right
+    fn application(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.atom()?.ok_or_else(|| self.expected("an expression"))?;
+        while !self.at_binding() && !self.at_arm_end() {
+            let Some(arg) = self.atom()? else { break };
+            expr = Expr::App(Box::new(expr), Box::new(arg));
+        }
+        Ok(expr)
+    }
+
+    /// `\x y -> body`, taking in as much of the expression as it can
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::Backslash, "`\\`")?.start;
+        let mut params = vec![];
+        while let Some(token @ Token { content: TokenContent::Ident(name), .. }) = self.peek() {
+            self.advance();
+            params.push((name.to_string(), token.start..token.end));
+        }
+        if params.is_empty() {
+            return Err(self.expected("a parameter name"));
+        }
+        self.expect(TokenContent::Arrow, "`->`")?;
+        let body = self.expression()?;
+        let mut lambda = body;
+        for (i, (param, param_span)) in params.into_iter().enumerate().rev() {
+            let start = if i == 0 { start } else { param_span.start };
+            lambda = Expr::Lam { param, param_span, body: Box::new(lambda), start };
+        }
+        Ok(lambda)
+    }
+
+    /// `let x = 1 y = 2 in body`, taking in as much of the expression as it
+    /// can
+    fn let_expression(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::Let, "`let`")?.start;
+        let mut bindings = vec![];
+        loop {
+            let (name, name_span) = self.declared_name()?;
+            self.expect(TokenContent::Equals, "`=`")?;
+            bindings.push((name, name_span, self.expression()?));
+            if !self.at_binding() {
+                break;
+            }
+        }
+        self.expect(TokenContent::In, "`in` after the `let` bindings")?;
+        let mut expr = self.expression()?;
+        for (i, (name, name_span, bound)) in bindings.into_iter().enumerate().rev() {
+            let start = if i == 0 { start } else { name_span.start };
+            expr = Expr::Let { name, name_span, bound: Box::new(bound), body: Box::new(expr), start };
+        }
+        Ok(expr)
+    }
+
+    /// `if cond then a else b`, where the `else` branch takes in as much of
+    /// the expression as it can
+    fn if_expression(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::If, "`if`")?.start;
+        let cond = self.expression()?;
+        self.expect(TokenContent::Then, "`then` after the condition")?;
+        let then_branch = self.expression()?;
+        self.expect(TokenContent::Else, "`else` after the `then` branch")?;
+        let else_branch = self.expression()?;
+        Ok(Expr::If {
+            cond: Box::new(cond),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+            start,
+        })
+    }
+
+    /// `case scrutinee of` and its arms. The arms each start a line, lined
+    /// up under the first; the first may instead follow the `of`, in which
+    /// case it's the only arm.
+    fn case_expression(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::Case, "`case`")?.start;
+        let scrutinee = self.expression()?;
+        self.expect(TokenContent::Of, "`of` after the scrutinee")?;
+        let first_arm = self.layout.get(self.pos).copied();
+        let mut arms = vec![];
+        loop {
+            let pattern = self.pattern()?;
+            self.expect(TokenContent::Arrow, "`->` after the pattern")?;
+            self.arm_columns.push(first_arm.map_or(0, |(_, column)| column));
+            let body = self.expression();
+            self.arm_columns.pop();
+            arms.push((pattern, body?));
+            match (first_arm, self.layout.get(self.pos)) {
+                (Some((true, column)), Some(&(true, next))) if next == column => {}
+                _ => break,
+            }
+        }
+        Ok(Expr::Case { scrutinee: Box::new(scrutinee), arms, start })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        let Some(token) = self.peek() else { return Err(self.expected("a pattern")) };
+        let span = token.start..token.end;
+        let pattern = match &token.content {
+            TokenContent::Integer(value) => Pattern::IntLit(*value, span),
+            TokenContent::String(value) | TokenContent::MultilineString(value) => {
+                Pattern::StringLit(value.clone(), span)
+            }
+            TokenContent::Ident(name) => Pattern::Var(name.to_string(), span),
+            TokenContent::Underscore => Pattern::Wildcard(span),
+            _ => return Err(self.expected("a pattern")),
+        };
+        self.advance();
+        Ok(pattern)
+    }
+
+    /// `[a, b, c]`, possibly empty, with an optional trailing comma
+    fn list(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::LBracket, "`[`")?.start;
+        let mut elements = vec![];
+        while self.peek().is_some_and(|t| t.content != TokenContent::RBracket) {
+            elements.push(self.expression()?);
+            if self.peek().is_some_and(|t| t.content == TokenContent::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        let end = self.expect(TokenContent::RBracket, "`,` or `]`")?.end;
+        Ok(Expr::List(elements, start..end))
+    }
+
+    /// `()`, `(a)`, or `(a, b, ...)`, told apart by their commas
+    fn parenthesized(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect(TokenContent::LParen, "`(`")?.start;
+        if let Some(close) = self.peek().filter(|t| t.content == TokenContent::RParen) {
+            self.advance();
+            return Ok(Expr::Tuple(vec![], start..close.end));
+        }
+        let mut elements = vec![self.expression()?];
+        while self.peek().is_some_and(|t| t.content == TokenContent::Comma) {
+            self.advance();
+            elements.push(self.expression()?);
+        }
+        let end = self.expect(TokenContent::RParen, "`,` or `)`")?.end;
+        if elements.len() == 1 {
+            return Ok(elements.remove(0));
+        }
+        Ok(Expr::Tuple(elements, start..end))
+    }
+
+    /// A literal, a name, a list, a lambda, a `let`, an `if`, a `case`, or a
+    /// parenthesized expression or tuple; `None` if the next token can't
+    /// start one
+    fn atom(&mut self) -> Result<Option<Expr>, ParseError> {
+        let Some(token) = self.peek() else { return Ok(None) };
+        let span = token.start..token.end;
+        let expr = match &token.content {
+            TokenContent::Integer(value) => Expr::IntLit(*value, span),
+            TokenContent::Float(value) => Expr::FloatLit(*value, span),
+            TokenContent::String(value) | TokenContent::MultilineString(value) => Expr::StringLit(value.clone(), span),
+            TokenContent::Ident(name) | TokenContent::Constructor(name) => Expr::Var(name.to_string(), span),
+            TokenContent::QualifiedName(segments) => Expr::Var(segments.join("."), span),
+            TokenContent::LBracket => return self.list().map(Some),
+            TokenContent::Backslash => return self.lambda().map(Some),
+            TokenContent::Let => return self.let_expression().map(Some),
+            TokenContent::If => return self.if_expression().map(Some),
+            TokenContent::Case => return self.case_expression().map(Some),
+            TokenContent::LParen => return self.parenthesized().map(Some),
+            _ => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(expr))
+    }
+}
+
+/// Every reserved word, in the order they're tried by the lexer
+pub const KEYWORDS: [&str; 13] =
+    ["module", "where", "import", "let", "in", "if", "then", "else", "case", "of", "infixl", "infixr", "infix"];
+
+/// The name declared by a leading `module Foo.Bar` header, if any
+pub fn header_module_name(tokens: &[Token]) -> Option<String> {
+    let mut significant = tokens.iter().filter(|t| is_significant(t));
+    if significant.next()?.content != TokenContent::Module {
+        return None;
+    }
+    match &significant.next()?.content {
+        TokenContent::Constructor(name) => Some(name.to_string()),
+        TokenContent::QualifiedName(segments) => Some(segments.join(".")),
+        _ => None,
+    }
+}
+
+/// Whether `name` is a legal identifier, i.e. it lexes as a single `Ident`
+pub fn is_identifier(name: &str) -> bool {
+    matches!(
+        lex_line(name).as_deref(),
+        Ok([Token { content: TokenContent::Ident(_), .. }, Token { content: TokenContent::Eof, .. }])
+    )
+}
+
+/// Groups a document's tokens into lines, each ending with its `Newline`.
+/// The offset of each line is where it starts in the document.
+pub fn split_lines(tokens: Vec<Token>) -> Vec<Tokens> {
+    let mut lines = vec![];
+    let mut line = vec![];
+    let mut offset = 0;
+    for token in tokens {
+        if line.is_empty() {
+            offset = token.position.location_offset();
+        }
+        let is_newline = token.content == TokenContent::Newline;
+        line.push(token);
+        if is_newline {
+            lines.push(Tokens::new(offset, std::mem::take(&mut line)));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(Tokens::new(offset, line));
+    }
+    lines
+}
+
+/// A top-level `name params = ...` definition found by scanning lines.
+/// Indented lines that follow belong to the same definition.
+pub struct DefinitionSite<'a, 'doc> {
+    pub name: &'a Token<'doc>,
+    pub params: Vec<&'a Token<'doc>>,
+    pub start: usize,
+    pub end: usize,
+}
+
+fn line_end(line: &[Token]) -> Option<usize> {
+    line.iter()
+        .rev()
+        .find(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_) | TokenContent::Eof))
+        .map(|t| t.end)
+}
+
+pub fn top_level_definitions<'a, 'doc>(tokens: &'a [Token<'doc>]) -> Vec<DefinitionSite<'a, 'doc>> {
+    let mut sites: Vec<DefinitionSite> = vec![];
+    let mut in_definition = false;
+    for line in tokens.split(|t| t.content == TokenContent::Newline) {
+        // blank lines don't end a definition
+        let Some(first) = line.first() else { continue };
+        match first.content {
+            TokenContent::Space(_) | TokenContent::Tab(_) => {
+                if let (true, Some(site), Some(end)) = (in_definition, sites.last_mut(), line_end(line)) {
+                    site.end = end;
+                }
+            }
+            TokenContent::Ident(_) => {
+                let lhs: Vec<_> = line
+                    .iter()
+                    .skip(1)
+                    .take_while(|t| t.content != TokenContent::Equals)
+                    .filter(|t| !matches!(t.content, TokenContent::Space(_) | TokenContent::Tab(_)))
+                    .collect();
+                let has_equals = line.iter().any(|t| t.content == TokenContent::Equals);
+                in_definition = has_equals && lhs.iter().all(|t| matches!(t.content, TokenContent::Ident(_)));
+                if in_definition {
+                    sites.push(DefinitionSite {
+                        name: first,
+                        params: lhs,
+                        start: first.start,
+                        end: line_end(line).unwrap_or(first.end),
+                    });
+                }
+            }
+            _ => in_definition = false,
+        }
+    }
+    sites
+}
+
+/// Parses every top-level declaration of a document that it can, skipping
+/// over broken declarations and reporting why each one couldn't be parsed.
+/// Tabs are `tab_width` columns wide when lining up indented blocks.
+pub fn parse_declarations(input: &str, tab_width: usize) -> (Vec<TopLevel>, Vec<ParseError>) {
+    let (tokens, mut errors) = lex_recovering(input);
+    // each line is sorted out on its own, then stitched together in order
+    let partials: Vec<PartialExpr> = split_lines(tokens).into_par_iter().map(parse_partial).collect();
+    let combined = partials.into_iter().fold(PartialExpr::Empty, combine_parts);
+    let (declarations, parse_errors) = complete_declarations(combined, tab_width);
+    errors.extend(parse_errors);
+    errors.sort_by_key(|e| e.offset());
+    (declarations, errors)
+}
+
+/// Parses every top-level declaration of a document, failing on the first
+/// that can't be parsed
+pub fn parse_top_level(input: &str) -> Result<Vec<TopLevel>, ParseError> {
+    match parse_declarations(input, DEFAULT_TAB_WIDTH) {
+        (_, errors) if !errors.is_empty() => Err(errors[0].clone()),
+        (declarations, _) => Ok(declarations),
+    }
+}
+
+/// Lexes `input` and parses all of it with `parse`
+fn parse_all<T>(input: &str, parse: impl FnOnce(&mut Parser) -> Result<T, ParseError>) -> Result<T, ParseError> {
+    // lines are lexed in parallel, falling back to the whole document when
+    // tokens span lines
+    let tokens = lex_lines(input)?;
+    let mut parser = Parser::new(&tokens);
+    let parsed = parse(&mut parser)?;
+    if !parser.at_end() {
+        return Err(parser.expected("the end of input"));
+    }
+    Ok(parsed)
+}
+
+pub fn parse_expr(input: &str) -> Result<Expr, ParseError> {
+    parse_all(input, |parser| parser.expression())
+}
+
+pub fn parse_definition(input: &str) -> Result<Spanned<Definition>, ParseError> {
+    parse_all(input, |parser| parser.definition())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types as lsp;
+
+    fn synthetic_document(lines: usize) -> String {
+        (0..lines)
+            .map(|i| match i % 4 {
+                0 => format!("value{i} = {i} + 2.5 -- note\n"),
+                1 => format!("  Data.List.map f{i} \"line {i}\"\r\n"),
+                2 => "\n".to_string(),
+                _ => format!("f{i} x = x {{- c -}} 'a'\n"),
+            })
+            .collect()
+    }
+
+    fn is_token_content(input: &str, given: TokenContent) { 
+        match lex_line(input) {
+            Ok(tokens) => {
+                assert_eq!(tokens.len(), 2);
+                let t = &tokens[0];
+                assert_eq!(t.content, given);
+                assert_eq!(tokens[1].content, TokenContent::Eof);
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    /// Lexes `input`, leaving off the `Eof` every lex ends with
+    fn lex_body(input: &str) -> Result<Vec<Token<'_>>, ParseError> {
+        let mut tokens = lex_line(input)?;
+        assert_eq!(tokens.pop().map(|t| t.content), Some(TokenContent::Eof));
+        Ok(tokens)
+    }
+
+    #[test]
+    fn lexing_ends_with_eof() {
+        for input in ["x", "module Foo where\n", "{- a\nb -}", "\u{feff}x = 1\n\n"] {
+            let tokens = lex_line(input).unwrap();
+            let eof = tokens.last().unwrap();
+            assert_eq!((eof.content.clone(), eof.start, eof.end), (TokenContent::Eof, input.len(), input.len()));
+            assert_eq!(tokens.iter().filter(|t| t.content == TokenContent::Eof).count(), 1);
+        }
+        let tagged = lex_all("x = 1\ny").unwrap();
+        assert_eq!(tagged.last().map(|(line, t)| (*line, t.content.clone(), t.start)), Some((1, TokenContent::Eof, 7)));
+    }
+
+    #[test]
+    fn can_lex_module() {
+        is_token_content("module", TokenContent::Module);
+    }
+
+    #[test]
+    fn can_lex_where() {
+        is_token_content("where", TokenContent::Where);
+    }
+
+    #[test]
+    fn can_lex_import() {
+        is_token_content("import", TokenContent::Import);
+        is_token_content("important", TokenContent::Ident("important"));
+    }
+
+    #[test]
+    fn can_lex_import_statement() {
+        match lex_body("import Data.List") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![
+                        TokenContent::Import,
+                        TokenContent::Space(1),
+                        TokenContent::QualifiedName(vec!["Data", "List"]),
+                    ]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_lex_qualified_member() {
+        is_token_content("Foo.Bar.baz", TokenContent::QualifiedName(vec!["Foo", "Bar", "baz"]));
+    }
+
+    #[test]
+    fn undotted_name_is_not_qualified() {
+        is_token_content("Foo", TokenContent::Constructor("Foo"));
+    }
+
+    #[test]
+    fn trailing_dot_is_lexed_separately() {
+        match lex_body("Foo.") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(contents, vec![TokenContent::Constructor("Foo"), TokenContent::Dot]);
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+        match lex_body("Foo.Bar.") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![TokenContent::QualifiedName(vec!["Foo", "Bar"]), TokenContent::Dot]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_lex_let() {
+        is_token_content("let", TokenContent::Let);
+    }
+
+    #[test]
+    fn can_lex_in() {
+        is_token_content("in", TokenContent::In);
+    }
+
+    #[test]
+    fn can_lex_conditional_keywords() {
+        is_token_content("if", TokenContent::If);
+        is_token_content("then", TokenContent::Then);
+        is_token_content("else", TokenContent::Else);
+        is_token_content("iffy", TokenContent::Ident("iffy"));
+        is_token_content("elsewhere", TokenContent::Ident("elsewhere"));
+    }
+
+    #[test]
+    fn can_lex_case_keywords() {
+        is_token_content("case", TokenContent::Case);
+        is_token_content("of", TokenContent::Of);
+        is_token_content("_", TokenContent::Underscore);
+        is_token_content("cases", TokenContent::Ident("cases"));
+        is_token_content("offset", TokenContent::Ident("offset"));
+    }
+
+    #[test]
+    fn keywords_need_a_word_boundary() {
+        is_token_content("letters", TokenContent::Ident("letters"));
+        is_token_content("inner", TokenContent::Ident("inner"));
+        is_token_content("modules", TokenContent::Ident("modules"));
+        is_token_content("where2", TokenContent::Ident("where2"));
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        let text = "\u{feff}answer = 42\n";
+        for tokens in [lex_body(text).unwrap(), lex_lines(text).unwrap(), lex_recovering(text).0] {
+            assert_eq!(tokens[0].content, TokenContent::Ident("answer"));
+            assert_eq!(tokens[0].start, 3);
+            assert_eq!(&text[tokens[0].start..tokens[0].end], "answer");
+        }
+        let (tokens, errors) = lex_recovering("\u{feff}x = @\ny = 1\n");
+        assert_eq!(errors, vec![ParseError::UnexpectedChar { offset: 7, found: '@' }]);
+        assert_eq!(tokens[0].content, TokenContent::Ident("y"));
+    }
+
+    #[test]
+    fn recovered_tokens_keep_their_lines() {
+        let text = "x = @\n  more\ny = 1\nz = `\nw = 2\n";
+        let (tokens, errors) = lex_recovering(text);
+        assert_eq!(errors.len(), 2);
+        let lines: Vec<_> = tokens
+            .iter()
+            .filter(|t| matches!(t.content, TokenContent::Ident(_)))
+            .map(|t| (&text[t.start..t.end], t.position.location_line()))
+            .collect();
+        assert_eq!(lines, vec![("y", 3), ("w", 5)]);
+        let lines: Vec<_> = lex_lines("a\nb\n").unwrap().iter().map(|t| t.position.location_line()).collect();
+        assert_eq!(lines, vec![1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn can_lex_arrow() {
+        is_token_content("->", TokenContent::Arrow);
+    }
+
+    #[test]
+    fn can_lex_fat_arrow() {
+        is_token_content("=>", TokenContent::FatArrow);
+    }
+
+    #[test]
+    fn can_lex_operators() {
+        is_token_content("+", TokenContent::Operator("+"));
+        is_token_content(">>=", TokenContent::Operator(">>="));
+        is_token_content("<>", TokenContent::Operator("<>"));
+        is_token_content("==", TokenContent::Operator("=="));
+    }
+
+    #[test]
+    fn reserved_operators_keep_their_tokens() {
+        is_token_content("->", TokenContent::Arrow);
+        is_token_content("=>", TokenContent::FatArrow);
+        is_token_content("=", TokenContent::Equals);
+        is_token_content("->>", TokenContent::Operator("->>"));
+    }
+
+    #[test]
+    fn can_lex_colon() {
+        is_token_content(":", TokenContent::Colon);
+    }
+
+    #[test]
+    fn can_lex_comma() {
+        is_token_content(",", TokenContent::Comma);
+    }
+
+    #[test]
+    fn can_lex_backslash() {
+        is_token_content("\\", TokenContent::Backslash);
+    }
+
+    #[test]
+    fn can_lex_double_colon() {
+        is_token_content("::", TokenContent::DoubleColon);
+    }
+
+    #[test]
+    fn can_lex_equals() {
+        is_token_content("=", TokenContent::Equals);
+    }
+
+    #[test]
+    fn can_lex_delimiters() {
+        is_token_content("(", TokenContent::LParen);
+        is_token_content(")", TokenContent::RParen);
+        is_token_content("[", TokenContent::LBracket);
+        is_token_content("]", TokenContent::RBracket);
+        is_token_content("{", TokenContent::LBrace);
+        is_token_content("}", TokenContent::RBrace);
+    }
+
+    #[test]
+    fn can_lex_parenthesized_name() {
+        match lex_body("(foo)") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![TokenContent::LParen, TokenContent::Ident("foo"), TokenContent::RParen]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn tokens_are_in_source_order() {
+        match lex_body("module foo") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![TokenContent::Module, TokenContent::Space(1), TokenContent::Ident("foo")]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unlexable_character_is_an_error() {
+        assert_eq!(lex_body("@"), Err(ParseError::UnexpectedChar { offset: 0, found: '@' }));
+    }
+
+    #[test]
+    fn lex_error_reports_offset() {
+        let err = lex_body("foo @ bar");
+        assert_eq!(err, Err(ParseError::UnexpectedChar { offset: 4, found: '@' }));
+        assert_eq!(err.unwrap_err().offset(), Some(4));
+    }
+
+    #[test]
+    fn resilient_lexing_reports_every_bad_character() {
+        let (tokens, errors) = lex_line_resilient("foo @ bar § baz");
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::UnexpectedChar { offset: 4, found: '@' },
+                ParseError::UnexpectedChar { offset: 10, found: '§' },
+            ]
+        );
+        let significant: Vec<_> = tokens.iter().filter(|t| !t.content.is_trivia()).map(|t| (t.content.clone(), t.start, t.end)).collect();
+        assert_eq!(
+            significant,
+            vec![
+                (TokenContent::Ident("foo"), 0, 3),
+                (TokenContent::Error('@'), 4, 5),
+                (TokenContent::Ident("bar"), 6, 9),
+                (TokenContent::Error('§'), 10, 12),
+                (TokenContent::Ident("baz"), 13, 16),
+                (TokenContent::Eof, 16, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn resilient_lexing_agrees_with_lex_line_on_good_input() {
+        let input = "x = 1 + 2\n";
+        assert_eq!(lex_line_resilient(input), (lex_line(input).unwrap(), vec![]));
+    }
+
+    #[test]
+    fn can_lex_newline() {
+        is_token_content("\n", TokenContent::Newline);
+    }
+
+    #[test]
+    fn crlf_separates_lines() {
+        let tokens = lex_body("module\r\nfoo").unwrap();
+        let contents: Vec<_> = tokens.iter().map(|t| t.content.clone()).collect();
+        assert_eq!(contents, vec![TokenContent::Module, TokenContent::Newline, TokenContent::Ident("foo")]);
+        assert_eq!((tokens[2].start, tokens[2].end), (8, 11));
+    }
+
+    #[test]
+    fn stray_carriage_return_is_space() {
+        let contents: Vec<_> = lex_body("foo\r").unwrap().into_iter().map(|t| t.content).collect();
+        assert_eq!(contents, vec![TokenContent::Ident("foo"), TokenContent::Space(1)]);
+    }
+
+    #[test]
+    fn newline_separates_lines() {
+        match lex_body("module foo\n  bar") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![
+                        TokenContent::Module,
+                        TokenContent::Space(1),
+                        TokenContent::Ident("foo"),
+                        TokenContent::Newline,
+                        TokenContent::Space(2),
+                        TokenContent::Ident("bar"),
+                    ]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn split_lines_keeps_newlines_and_line_offsets() {
+        match lex_body("foo\n  bar\n") {
+            Ok(tokens) => {
+                let lines = split_lines(tokens);
+                assert_eq!(lines.len(), 2);
+                assert_eq!(lines[0].offset, 0);
+                assert_eq!(lines[0].tokens.last().map(|t| &t.content), Some(&TokenContent::Newline));
+                assert_eq!(lines[1].offset, 4);
+                assert_eq!(lines[1].tokens[0].content, TokenContent::Space(2));
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn parallel_lexing_matches_serial_lexing() {
+        let document = synthetic_document(4000);
+        assert_eq!(lex_lines(&document), lex_body(&document));
+    }
+
+    #[test]
+    fn parallel_lexing_handles_tokens_spanning_lines() {
+        let document = format!("{}{{- spans\nlines -}}\n{}", synthetic_document(100), synthetic_document(100));
+        assert_eq!(lex_lines(&document), lex_body(&document));
+    }
+
+    #[test]
+    fn parallel_lexing_reports_errors_on_their_line() {
+        let mut document = synthetic_document(1000);
+        let line_start = document.len();
+        document.push_str("oops @\n");
+        document.push_str(&synthetic_document(1000));
+        assert_eq!(
+            lex_lines(&document),
+            Err(ParseError::UnexpectedChar { offset: line_start + 5, found: '@' })
+        );
+    }
+
+    #[test]
+    fn tokens_record_their_start_offset() {
+        match lex_body("module foo") {
+            Ok(tokens) => {
+                let offsets: Vec<_> = tokens.iter().map(|t| t.position.location_offset()).collect();
+                assert_eq!(offsets, vec![0, 6, 7]);
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn tokens_record_their_extent() {
+        match lex_body("\"hi\" foo") {
+            Ok(tokens) => {
+                assert_eq!((tokens[0].start, tokens[0].end), (0, 4));
+                assert_eq!((tokens[1].start, tokens[1].end), (4, 5));
+                assert_eq!((tokens[2].start, tokens[2].end), (5, 8));
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_find_top_level_definitions() {
+        let text = "module Foo where\n\nanswer = 42\n\nadd x y =\n  x + y\n\n  -- still add\n-- done\nnot a definition\n";
+        let tokens = lex_body(text).unwrap();
+        let sites = top_level_definitions(&tokens);
+        let names: Vec<_> = sites.iter().map(|s| &s.name.content).collect();
+        assert_eq!(names, vec![&TokenContent::Ident("answer"), &TokenContent::Ident("add")]);
+        assert_eq!(&text[sites[0].start..sites[0].end], "answer = 42");
+        assert_eq!(&text[sites[1].start..sites[1].end], "add x y =\n  x + y\n\n  -- still add");
+        assert_eq!(sites[1].params.len(), 2);
+    }
+
+    #[test]
+    fn can_check_identifiers() {
+        assert!(is_identifier("foo"));
+        assert!(is_identifier("fooBar2"));
+        assert!(!is_identifier("Foo"));
+        assert!(!is_identifier("2foo"));
+        assert!(!is_identifier("foo bar"));
+        assert!(!is_identifier("let"));
+        assert!(!is_identifier(""));
+    }
+
+    #[test]
+    fn can_find_header_module_name() {
+        let tokens = lex_body("-- about\nmodule Data.List where\n").unwrap();
+        assert_eq!(header_module_name(&tokens), Some("Data.List".to_string()));
+        let tokens = lex_body("module Foo where").unwrap();
+        assert_eq!(header_module_name(&tokens), Some("Foo".to_string()));
+        let tokens = lex_body("x = 1").unwrap();
+        assert_eq!(header_module_name(&tokens), None);
+    }
+
+    #[test]
+    fn can_lex_space() {
+        match lex_body(" ") {
+            Ok(tokens) => {
+                assert_eq!(tokens.len(), 1);
+                let t = &tokens[0];
+                assert_eq!(t.content, TokenContent::Space(1))
+            },
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn spaces_have_correct_length() {
+        match lex_body("    ") {
+            Ok(tokens) => {
+                assert_eq!(tokens.len(), 1);
+                let t = &tokens[0];
+                assert_eq!(t.content, TokenContent::Space(4))
+            },
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_lex_tabs() {
+        is_token_content("\t\t", TokenContent::Tab(2));
+        let contents: Vec<_> = lex_body(" \t ").unwrap().into_iter().map(|t| t.content).collect();
+        assert_eq!(contents, vec![TokenContent::Space(1), TokenContent::Tab(1), TokenContent::Space(1)]);
+    }
+
+    #[test]
+    fn tabs_indent_to_the_next_tab_stop() {
+        let width = |line: &str, tab_width| indentation(&lex_body(line).unwrap(), tab_width);
+        assert_eq!(width("\tx", 4), width("    x", 4));
+        assert_eq!(width("\tx", 8), width("        x", 8));
+        assert!(width("\tx", 4) > width("  x", 4));
+        assert_eq!(width("  \tx", 4), 4);
+        assert_eq!(width("\t  \tx", 2), 6);
+        assert_eq!(width("x", 4), 0);
+    }
+
+    #[test]
+    fn can_lex_ident() {
+        is_token_content("hello", TokenContent::Ident("hello"));
+    }
+
+    #[test]
+    fn can_lex_constructor() {
+        is_token_content("Foo", TokenContent::Constructor("Foo"));
+    }
+
+    #[test]
+    fn names_can_contain_digits() {
+        is_token_content("foo123", TokenContent::Ident("foo123"));
+        is_token_content("Foo123", TokenContent::Constructor("Foo123"));
+    }
+
+    #[test]
+    fn names_keep_inner_capitals() {
+        is_token_content("fooBar", TokenContent::Ident("fooBar"));
+        is_token_content("FooBar", TokenContent::Constructor("FooBar"));
+    }
+
+    #[test]
+    fn can_lex_integer() {
+        is_token_content("0", TokenContent::Integer(0));
+        is_token_content("42", TokenContent::Integer(42));
+        is_token_content("1000000", TokenContent::Integer(1000000));
+    }
+
+    #[test]
+    fn can_lex_separated_numbers() {
+        is_token_content("1_000_000", TokenContent::Integer(1_000_000));
+        is_token_content("-1_0", TokenContent::Integer(-10));
+        is_token_content("0xFF_FF", TokenContent::Integer(0xFFFF));
+        is_token_content("0x2a", TokenContent::Integer(42));
+        is_token_content("1_000.000_5", TokenContent::Float(1_000.000_5));
+        is_token_content("1e1_0", TokenContent::Float(1e10));
+    }
+
+    #[test]
+    fn misplaced_separators_are_rejected() {
+        for input in ["_1", "1_", "1__0", "0x_FF", "0xFF_", "1._5", "1_.5"] {
+            let lexed = lex_body(input);
+            assert!(
+                !matches!(lexed.as_deref(), Ok([Token { content: TokenContent::Integer(_) | TokenContent::Float(_), .. }])),
+                "`{}` lexed as {:?}",
+                input,
+                lexed
+            );
+        }
+        assert_eq!(lex_body("1__0"), Err(ParseError::UnexpectedChar { offset: 0, found: '1' }));
+    }
+
+    #[test]
+    fn can_lex_float() {
+        is_token_content("2.75", TokenContent::Float(2.75));
+        is_token_content("0.5", TokenContent::Float(0.5));
+    }
+
+    #[test]
+    fn can_lex_float_with_exponent() {
+        is_token_content("1.0e10", TokenContent::Float(1.0e10));
+        is_token_content("2.5E-3", TokenContent::Float(2.5e-3));
+        is_token_content("1e10", TokenContent::Float(1e10));
+    }
+
+    #[test]
+    fn trailing_dot_is_not_a_float() {
+        assert!(lex_float(Span::new("3.")).is_err());
+        match lexer(Span::new("3.")) {
+            Ok((rest, token)) => {
+                assert_eq!(token.content, TokenContent::Integer(3));
+                assert_eq!(*rest.fragment(), ".");
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_lex_negative_numbers() {
+        is_token_content("-5", TokenContent::Integer(-5));
+        is_token_content("-2.75", TokenContent::Float(-2.75));
+    }
+
+    #[test]
+    fn spaced_minus_is_not_a_negative_literal() {
+        assert!(lex_integer(Span::new("- 5")).is_err());
+        assert!(lex_float(Span::new("- 5.0")).is_err());
+        match lex_body("a - 5") {
+            Ok(tokens) => {
+                let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+                assert_eq!(
+                    contents,
+                    vec![
+                        TokenContent::Ident("a"),
+                        TokenContent::Space(1),
+                        TokenContent::Operator("-"),
+                        TokenContent::Space(1),
+                        TokenContent::Integer(5),
+                    ]
+                );
+            }
+            Err(e) => panic!("Unexpected lexing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unspaced_minus_after_an_operand_is_subtraction() {
+        for (input, operand, subtracted) in [("a-1", TokenContent::Ident("a"), 1), ("3-2", TokenContent::Integer(3), 2)] {
+            let contents: Vec<_> = lex_body(input).unwrap().into_iter().map(|t| t.content).collect();
+            assert_eq!(contents, vec![operand, TokenContent::Operator("-"), TokenContent::Integer(subtracted)]);
+        }
+        assert_eq!(sexpr(&parse_expr("a-1").unwrap()), "(a - 1)");
+        // after an operator, an opening bracket or a comma it's still a sign
+        let contents: Vec<_> = lex_body("(-1,[-2])").unwrap().into_iter().map(|t| t.content).collect();
+        assert_eq!(
+            contents,
+            vec![
+                TokenContent::LParen,
+                TokenContent::Integer(-1),
+                TokenContent::Comma,
+                TokenContent::LBracket,
+                TokenContent::Integer(-2),
+                TokenContent::RBracket,
+                TokenContent::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn can_lex_char() {
+        is_token_content("'a'", TokenContent::Char('a'));
+        is_token_content("'\"'", TokenContent::Char('"'));
+    }
+
+    #[test]
+    fn can_lex_escaped_char() {
+        is_token_content("'\\n'", TokenContent::Char('\n'));
+        is_token_content("'\\t'", TokenContent::Char('\t'));
+        is_token_content("'\\''", TokenContent::Char('\''));
+        is_token_content("'\\\\'", TokenContent::Char('\\'));
+    }
+
+    #[test]
+    fn empty_char_is_an_error() {
+        assert_eq!(lex_body("''"), Err(ParseError::UnexpectedChar { offset: 0, found: '\'' }));
+    }
+
+    #[test]
+    fn multi_character_char_is_an_error() {
+        assert_eq!(lex_body("'ab'"), Err(ParseError::UnexpectedChar { offset: 0, found: '\'' }));
+    }
+
+    #[test]
+    fn can_lex_single_line_string() {
+        is_token_content("\"hello\"", TokenContent::String("hello".to_string()));
+    }
+
+    #[test]
+    fn can_lex_empty_string() {
+        is_token_content("\"\"", TokenContent::String("".to_string()));
+    }
+
+    #[test]
+    fn can_lex_string_with_escaped_quotes() {
+        is_token_content(
+            r#""he said \"hi\"""#,
+            TokenContent::String(r#"he said "hi""#.to_string()),
+        );
+    }
+
+    #[test]
+    fn can_lex_string_with_escaped_backslash() {
+        is_token_content(r#""a\\b""#, TokenContent::String(r"a\b".to_string()));
+    }
+
+    #[test]
+    fn can_lex_string_with_newline_escape() {
+        is_token_content(r#""one\ntwo\tthree""#, TokenContent::String("one\ntwo\tthree".to_string()));
+    }
+
+    #[test]
+    fn can_lex_multiline_string_on_one_line() {
+        is_token_content("\"\"\"x\"\"\"", TokenContent::MultilineString("x".to_string()));
+    }
+
+    #[test]
+    fn can_lex_multiline_string() {
+        is_token_content(
+            "\"\"\"first\n  \"second\"\\n\n\"\"\"",
+            TokenContent::MultilineString("first\n  \"second\"\\n\n".to_string()),
+        );
+    }
+
+    #[test]
+    fn unterminated_multiline_string_is_an_error() {
+        assert_eq!(lex_body("\"\"\"hello\nworld\"\""), Err(ParseError::UnexpectedChar { offset: 0, found: '"' }));
+    }
+
+    #[test]
+    fn unterminated_string_is_an_error() {
+        assert_eq!(lex_body("\"hello"), Err(ParseError::UnexpectedChar { offset: 0, found: '"' }));
+    }
+
+    #[test]
+    fn can_lex_line_comment() {
+        is_token_content("-- hello", TokenContent::LineComment(" hello"));
+    }
+
+    #[test]
+    fn can_lex_empty_line_comment() {
+        is_token_content("--", TokenContent::LineComment(""));
+    }
+
+    #[test]
+    fn can_lex_block_comment() {
+        is_token_content("{- hello -}", TokenContent::BlockComment(" hello "));
+    }
+
+    #[test]
+    fn can_lex_nested_block_comment() {
+        is_token_content(
+            "{- outer {- inner -} still outer -}",
+            TokenContent::BlockComment(" outer {- inner -} still outer "),
+        );
+    }
+
+    #[test]
+    fn can_lex_multi_line_block_comment() {
+        is_token_content("{- one\ntwo -}", TokenContent::BlockComment(" one\ntwo "));
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_an_error() {
+        assert_eq!(lex_body("{- outer {- inner -}"), Err(ParseError::UnexpectedChar { offset: 0, found: '{' }));
+    }
+
+    /// Renders an expression's shape, ignoring spans
+    fn sexpr(expr: &Expr) -> String {
+        match expr {
+            Expr::IntLit(value, _) => value.to_string(),
+            Expr::FloatLit(value, _) => value.to_string(),
+            Expr::StringLit(value, _) => format!("{:?}", value),
+            Expr::Var(name, _) => name.clone(),
+            Expr::List(elements, _) => format!("[{}]", elements.iter().map(sexpr).collect::<Vec<_>>().join(", ")),
+            Expr::Tuple(elements, _) => format!("<{}>", elements.iter().map(sexpr).collect::<Vec<_>>().join(", ")),
+            Expr::App(func, arg) => format!("({} {})", sexpr(func), sexpr(arg)),
+            Expr::BinOp(op, lhs, rhs) => format!("({} {} {})", sexpr(lhs), op, sexpr(rhs)),
+            Expr::Lam { param, body, .. } => format!("(\\{} -> {})", param, sexpr(body)),
+            Expr::Let { name, bound, body, .. } => format!("(let {} = {} in {})", name, sexpr(bound), sexpr(body)),
+            Expr::If { cond, then_branch, else_branch, .. } => {
+                format!("(if {} then {} else {})", sexpr(cond), sexpr(then_branch), sexpr(else_branch))
+            }
+            Expr::Case { scrutinee, arms, .. } => {
+                let arms: Vec<_> = arms.iter().map(|(pattern, body)| format!("{:?} -> {}", pattern, sexpr(body))).collect();
+                format!("(case {} of {})", sexpr(scrutinee), arms.join("; "))
+            }
+            Expr::Where { body, bindings } => {
+                let bindings: Vec<_> = bindings.iter().map(|b| format!("{} = {}", b.name, sexpr(&b.def_expr))).collect();
+                format!("({} where {})", sexpr(body), bindings.join("; "))
+            }
+        }
+    }
+
+    fn parses_to(input: &str, expected: &str) {
+        match parse_expr(input) {
+            Ok(expr) => assert_eq!(sexpr(&expr), expected),
+            Err(e) => panic!("Unexpected parsing error! {:?}", e),
+        }
+    }
+
+    #[test]
+    fn can_parse_integer() {
+        assert_eq!(parse_expr("42"), Ok(Expr::IntLit(42, 0..2)));
+    }
+
+    #[test]
+    fn can_parse_float() {
+        assert_eq!(parse_expr("2.5"), Ok(Expr::FloatLit(2.5, 0..3)));
+        parses_to("f 1.5e3", "(f 1500)");
+    }
+
+    #[test]
+    fn can_parse_string() {
+        assert_eq!(parse_expr("\"hi\""), Ok(Expr::StringLit("hi".to_string(), 0..4)));
+    }
+
+    #[test]
+    fn can_parse_var() {
+        assert_eq!(parse_expr("foo"), Ok(Expr::Var("foo".to_string(), 0..3)));
+    }
+
+    #[test]
+    fn can_parse_empty_list() {
+        assert_eq!(parse_expr("[ ]"), Ok(Expr::List(vec![], 0..3)));
+    }
+
+    #[test]
+    fn can_parse_list() {
+        let expr = parse_expr("[1, 2, 3]").unwrap();
+        assert_eq!(sexpr(&expr), "[1, 2, 3]");
+        assert_eq!(expr.span(), 0..9);
+        parses_to("f [x,\n  y,\n]", "(f [x, y])");
+    }
+
+    #[test]
+    fn can_parse_nested_list() {
+        parses_to("[[1], [2, f x], []]", "[[1], [2, (f x)], []]");
+    }
+
+    #[test]
+    fn list_needs_closing_bracket() {
+        assert!(parse_expr("[1, 2").is_err());
+        assert!(parse_expr("[,]").is_err());
+    }
+
+    #[test]
+    fn can_parse_unit() {
+        assert_eq!(parse_expr("( )"), Ok(Expr::Tuple(vec![], 0..3)));
+    }
+
+    #[test]
+    fn single_parenthesized_expression_is_not_a_tuple() {
+        assert_eq!(parse_expr("(1)"), Ok(Expr::IntLit(1, 1..2)));
+    }
+
+    #[test]
+    fn can_parse_tuple() {
+        let expr = parse_expr("(1, 2, 3)").unwrap();
+        assert_eq!(sexpr(&expr), "<1, 2, 3>");
+        assert_eq!(expr.span(), 0..9);
+        parses_to("f (x, (y, g z)) ()", "((f <x, <y, (g z)>>) <>)");
+        assert!(parse_expr("(1,)").is_err());
+    }
+
+    #[test]
+    fn can_parse_application() {
+        let expr = parse_expr("f x").unwrap();
+        assert_eq!(sexpr(&expr), "(f x)");
+        assert_eq!(expr.span(), 0..3);
+    }
+
+    #[test]
+    fn application_is_left_associative() {
+        parses_to("f x y", "((f x) y)");
+        parses_to("f (g x) \"s\"", "((f (g x)) \"s\")");
+        parses_to("  Data.List.map f\n    xs -- done", "((Data.List.map f) xs)");
+    }
+
+    #[test]
+    fn unclosed_paren_is_an_error() {
+        assert_eq!(
+            parse_expr("f (x"),
+            Err(ParseError::Expected { offset: 4, len: 0, expected: "`,` or `)`".to_string() })
+        );
+    }
+
+    #[test]
+    fn trailing_tokens_are_an_error() {
+        assert_eq!(
+            parse_expr("f x )"),
+            Err(ParseError::Expected { offset: 4, len: 1, expected: "the end of input".to_string() })
+        );
+    }
+
+    #[test]
+    fn can_parse_definition() {
+        assert_eq!(
+            parse_definition("  answer = 42  "),
+            Ok(Spanned::new(
+                Definition {
+                    name: "answer".to_string(),
+                    name_span: 2..8,
+                    def_type: None,
+                    def_expr: Expr::IntLit(42, 11..13),
+                },
+                2..13
+            ))
+        );
+        assert_eq!(
+            parse_definition("greeting = \"hi\""),
+            Ok(Spanned::new(
+                Definition {
+                    name: "greeting".to_string(),
+                    name_span: 0..8,
+                    def_type: None,
+                    def_expr: Expr::StringLit("hi".to_string(), 11..15),
+                },
+                0..15
+            ))
+        );
+    }
+
+    #[test]
+    fn definition_params_become_lambdas() {
+        let definition = parse_definition("add x y = x").unwrap();
+        assert_eq!(definition.name, "add");
+        assert_eq!(sexpr(&definition.def_expr), "(\\x -> (\\y -> x))");
+        assert_eq!(definition.def_expr.span(), 4..11);
+        assert_eq!(definition.span(), 0..11);
+    }
+
+    #[test]
+    fn can_parse_a_where_clause() {
+        let definition = parse_definition("f x = y where y = x + 1").unwrap();
+        assert_eq!(sexpr(&definition.def_expr), "(\\x -> (y where y = (x + 1)))");
+        let Expr::Lam { body, .. } = &definition.def_expr else { panic!("expected a lambda") };
+        let Expr::Where { bindings, .. } = &**body else { panic!("expected a where clause") };
+        assert_eq!((bindings[0].name.as_str(), bindings[0].span()), ("y", 14..23));
+        assert_eq!(definition.span(), 0..23);
+    }
+
+    #[test]
+    fn where_bindings_are_delimited_by_indentation() {
+        let declarations = parse_top_level("f = g 1\n  where\n    g x = h\n      x\n    h = 2\nmain = f\n").unwrap();
+        let shapes: Vec<_> = declarations
+            .iter()
+            .map(|d| match d {
+                TopLevel::Definition(d) => format!("{} = {}", d.name, sexpr(&d.def_expr)),
+                _ => panic!("expected only definitions"),
+            })
+            .collect();
+        assert_eq!(shapes, vec!["f = ((g 1) where g = (\\x -> (h x)); h = 2)", "main = f"]);
+    }
+
+    #[test]
+    fn where_needs_a_binding() {
+        assert_eq!(
+            parse_definition("f = y where"),
+            Err(ParseError::Expected { offset: 11, len: 0, expected: "a definition name".to_string() })
+        );
+    }
+
+    #[test]
+    fn subexpressions_know_their_spans() {
+        let expr = parse_expr("f (g x)").unwrap();
+        let Expr::App(func, arg) = &expr else { panic!("expected an application") };
+        assert_eq!(func.span(), 0..1);
+        assert_eq!(arg.span(), 3..6);
+        let Expr::App(g, x) = &**arg else { panic!("expected an application") };
+        assert_eq!((g.span(), x.span()), (3..4, 5..6));
+        assert_eq!(expr.span(), 0..6);
+    }
+
+    #[test]
+    fn definitions_span_every_line() {
+        let declarations = parse_top_level("main =\n  f\n    (g x) -- done\n").unwrap();
+        let [TopLevel::Definition(definition)] = &declarations[..] else { panic!("expected one definition") };
+        assert_eq!(definition.span(), 0..20);
+    }
+
+    #[test]
+    fn definition_without_equals_is_an_error() {
+        assert_eq!(
+            parse_definition("answer 42"),
+            Err(ParseError::Expected { offset: 7, len: 2, expected: "`=`".to_string() })
+        );
+    }
+
+    #[test]
+    fn can_parse_signature() {
+        assert_eq!(
+            parse_top_level("answer : Int"),
+            Ok(vec![TopLevel::Signature(Signature {
+                name: "answer".to_string(),
+                name_span: 0..6,
+                ty: Expr::Var("Int".to_string(), 9..12),
+            })])
+        );
+    }
+
+    #[test]
+    fn top_level_declarations_take_indented_lines() {
+        let declarations = parse_top_level("-- numbers\nanswer : Int\nanswer =\n  add 40\n\n    2\nzero = 0\n").unwrap();
+        let shapes: Vec<_> = declarations
+            .iter()
+            .map(|d| match d {
+                TopLevel::Header(h) => format!("module {}", h.name),
+                TopLevel::Import(i) => format!("import {}", i.name.value),
+                TopLevel::Signature(s) => format!("{} : {}", s.name, sexpr(&s.ty)),
+                TopLevel::Fixity(f) => format!("fixity {}", f.operator),
+                TopLevel::Definition(d) => format!("{} = {}", d.name, sexpr(&d.def_expr)),
+            })
+            .collect();
+        assert_eq!(shapes, vec!["answer : Int", "answer = ((add 40) 2)", "zero = 0"]);
+    }
+
+    #[test]
+    fn can_parse_module_header() {
+        assert_eq!(
+            parse_top_level("module Data.List where"),
+            Ok(vec![TopLevel::Header(ModuleHeader { name: "Data.List".to_string(), name_span: 7..16, exports: None })])
+        );
+    }
+
+    #[test]
+    fn module_header_can_list_exports() {
+        let declarations = parse_top_level("module Data.List (map, Empty) where").unwrap();
+        let TopLevel::Header(header) = &declarations[0] else { panic!("expected a header") };
+        let exports: Vec<_> = header.exports.iter().flatten().map(|e| (e.name.as_str(), e.span.clone())).collect();
+        assert_eq!(exports, vec![("map", 18..21), ("Empty", 23..28)]);
+        assert_eq!(
+            parse_top_level("module Data.List () where"),
+            Err(ParseError::Expected { offset: 18, len: 1, expected: "a name to export".to_string() })
+        );
+    }
+
+    #[test]
+    fn module_header_needs_a_name() {
+        assert_eq!(
+            parse_top_level("module where"),
+            Err(ParseError::Expected { offset: 7, len: 5, expected: "a module name like `Data.List`".to_string() })
+        );
+        assert_eq!(
+            parse_top_level("module Data.map where"),
+            Err(ParseError::Expected { offset: 7, len: 8, expected: "a module name like `Data.List`".to_string() })
+        );
+    }
+
+    #[test]
+    fn module_header_needs_where() {
+        assert_eq!(
+            parse_top_level("module Main\nx = 1"),
+            Err(ParseError::Expected { offset: 11, len: 0, expected: "`where`".to_string() })
+        );
+    }
+
+    fn parse_import(input: &str) -> Result<Import, ParseError> {
+        parse_all(input, |parser| parser.import())
+    }
+
+    fn imported(name: &str, span: Range<usize>) -> ImportedName {
+        ImportedName { name: name.to_string(), span }
+    }
+
+    #[test]
+    fn can_parse_wildcard_import() {
+        let import = parse_import("import Data.List").unwrap();
+        assert_eq!(import.name.value, "Data.List");
+        assert_eq!(import.reference, ModuleReference::WildCard);
+        assert_eq!(import.span, 0..16);
+    }
+
+    #[test]
+    fn can_parse_single_import() {
+        let import = parse_import("import Foo (bar)").unwrap();
+        assert_eq!(import.name.value, "Foo");
+        assert_eq!(import.reference, ModuleReference::Single(imported("bar", 12..15)));
+        assert_eq!(import.span, 0..16);
+    }
+
+    #[test]
+    fn can_parse_many_import() {
+        let import = parse_import("import Foo (bar, Baz)").unwrap();
+        assert_eq!(
+            import.reference,
+            ModuleReference::Many(vec![imported("bar", 12..15), imported("Baz", 17..20)])
+        );
+    }
+
+    #[test]
+    fn unclosed_import_list_is_an_error() {
+        assert_eq!(
+            parse_import("import Foo (bar baz)"),
+            Err(ParseError::Expected { offset: 16, len: 3, expected: "`,` or `)`".to_string() })
+        );
+    }
+
+    #[test]
+    fn operators_respect_precedence() {
+        parses_to("1 + 2 * 3", "(1 + (2 * 3))");
+        parses_to("1 * 2 + 3", "((1 * 2) + 3)");
+        parses_to("f x + g y", "((f x) + (g y))");
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let expr = parse_expr("(1 + 2) * 3").unwrap();
+        assert_eq!(sexpr(&expr), "((1 + 2) * 3)");
+        assert_eq!(expr.span(), 1..11);
+    }
+
+    #[test]
+    fn operators_respect_associativity() {
+        parses_to("1 - 2 - 3", "((1 - 2) - 3)");
+        parses_to("a ++ b ++ c", "(a ++ (b ++ c))");
+        parses_to("2 ^ 3 ^ 2", "(2 ^ (3 ^ 2))");
+        parses_to("a == b && c < d", "((a == b) && (c < d))");
+    }
+
+    /// The body of each definition in `input`, with declared fixities applied
+    fn definition_bodies(input: &str) -> Vec<String> {
+        parse_top_level(input)
+            .unwrap()
+            .iter()
+            .filter_map(|d| match d {
+                TopLevel::Definition(d) => Some(sexpr(&d.def_expr)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn declared_fixities_change_associativity() {
+        parses_to("a <+> b <+> c", "((a <+> b) <+> c)");
+        assert_eq!(definition_bodies("infixr 5 <+>\nx = a <+> b <+> c\n"), vec!["(a <+> (b <+> c))"]);
+        assert_eq!(definition_bodies("infixl 5 ++\nx = a ++ b ++ c\n"), vec!["((a ++ b) ++ c)"]);
+    }
+
+    #[test]
+    fn declared_fixities_change_precedence() {
+        // uses above the declaration still see it
+        assert_eq!(
+            definition_bodies("x = a <+> b * c\ny = a + b <+> c\ninfix 8 <+>\n"),
+            vec!["((a <+> b) * c)", "(a + (b <+> c))"]
+        );
+    }
+
+    #[test]
+    fn can_parse_fixity_declarations() {
+        let fixities: Vec<_> = parse_top_level("infixl 6 +\ninfixr 0 <|\ninfix 4 ==\n")
+            .unwrap()
+            .into_iter()
+            .map(|d| match d {
+                TopLevel::Fixity(f) => (f.assoc, f.precedence, f.operator),
+                other => panic!("expected a fixity, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            fixities,
+            vec![
+                (Associativity::Left, 6, "+".to_string()),
+                (Associativity::Right, 0, "<|".to_string()),
+                (Associativity::None, 4, "==".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fixity_needs_a_precedence_and_an_operator() {
+        assert_eq!(
+            parse_top_level("infixl 10 +"),
+            Err(ParseError::Expected { offset: 7, len: 2, expected: "a precedence from 0 to 9".to_string() })
+        );
+        assert_eq!(
+            parse_top_level("infixr 5 plus"),
+            Err(ParseError::Expected { offset: 9, len: 4, expected: "an operator".to_string() })
+        );
+    }
+
+    #[test]
+    fn can_lex_fixity_keywords() {
+        is_token_content("infixl", TokenContent::Infixl);
+        is_token_content("infixr", TokenContent::Infixr);
+        is_token_content("infix", TokenContent::Infix);
+        is_token_content("infixes", TokenContent::Ident("infixes"));
+    }
+
+    #[test]
+    fn non_associative_operators_cannot_chain() {
+        assert_eq!(
+            parse_expr("a == b == c"),
+            Err(ParseError::Expected {
+                offset: 7,
+                len: 2,
+                expected: "parentheses to group `==` with `==`".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn can_parse_lambda() {
+        assert_eq!(
+            parse_expr("\\x -> x"),
+            Ok(Expr::Lam {
+                param: "x".to_string(),
+                param_span: 1..2,
+                body: Box::new(Expr::Var("x".to_string(), 6..7)),
+                start: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn lambda_parameters_nest() {
+        let expr = parse_expr("\\x y -> x").unwrap();
+        assert_eq!(sexpr(&expr), "(\\x -> (\\y -> x))");
+        let Expr::Lam { body, .. } = &expr else { panic!("expected a lambda") };
+        assert_eq!(expr.span(), 0..9);
+        assert_eq!(body.span(), 3..9);
+    }
+
+    #[test]
+    fn lambda_body_extends_right() {
+        parses_to("map (\\x -> x + 1) xs", "((map (\\x -> (x + 1))) xs)");
+        parses_to("f \\x -> x * 2", "(f (\\x -> (x * 2)))");
+    }
+
+    #[test]
+    fn lambda_needs_parameters_and_arrow() {
+        assert_eq!(
+            parse_expr("\\ -> x"),
+            Err(ParseError::Expected { offset: 2, len: 2, expected: "a parameter name".to_string() })
+        );
+        assert_eq!(
+            parse_expr("\\x = x"),
+            Err(ParseError::Expected { offset: 3, len: 1, expected: "`->`".to_string() })
+        );
+    }
+
+    #[test]
+    fn can_parse_let() {
+        let expr = parse_expr("let x = 1 in x").unwrap();
+        assert_eq!(sexpr(&expr), "(let x = 1 in x)");
+        assert_eq!(expr.span(), 0..14);
+    }
+
+    #[test]
+    fn let_bindings_nest() {
+        let expr = parse_expr("let x = f 1\n    y = x + 2\nin x * y").unwrap();
+        assert_eq!(sexpr(&expr), "(let x = (f 1) in (let y = (x + 2) in (x * y)))");
+        let Expr::Let { body, .. } = &expr else { panic!("expected a let") };
+        assert_eq!(body.span(), 16..34);
+    }
+
+    #[test]
+    fn let_without_in_is_an_error() {
+        assert_eq!(
+            parse_expr("let x = 1 x"),
+            Err(ParseError::Expected { offset: 11, len: 0, expected: "`in` after the `let` bindings".to_string() })
+        );
+    }
+
+    fn declared_names(declarations: &[TopLevel]) -> Vec<&str> {
+        declarations
+            .iter()
+            .filter_map(|d| match d {
+                TopLevel::Definition(d) => Some(d.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn can_parse_if() {
+        let expr = parse_expr("if x then 1 else 2").unwrap();
+        assert_eq!(sexpr(&expr), "(if x then 1 else 2)");
+        assert_eq!(expr.span(), 0..18);
+        parses_to("f (if a b then c else d + 1) e", "((f (if (a b) then c else (d + 1))) e)");
+    }
+
+    #[test]
+    fn if_needs_then_and_else() {
+        assert_eq!(
+            parse_expr("if x then 1"),
+            Err(ParseError::Expected { offset: 11, len: 0, expected: "`else` after the `then` branch".to_string() })
+        );
+        assert_eq!(
+            parse_expr("if x 1 else 2"),
+            Err(ParseError::Expected { offset: 7, len: 4, expected: "`then` after the condition".to_string() })
+        );
+    }
+
+    #[test]
+    fn can_parse_case() {
+        let expr = parse_expr("case n of\n  0 -> \"zero\"\n  m -> f m\n").unwrap();
+        let Expr::Case { scrutinee, arms, start } = &expr else { panic!("expected a case") };
+        assert_eq!(**scrutinee, Expr::Var("n".to_string(), 5..6));
+        assert_eq!(*start, 0);
+        assert_eq!(arms.len(), 2);
+        assert_eq!(arms[0].0, Pattern::IntLit(0, 12..13));
+        assert_eq!(arms[0].1, Expr::StringLit("zero".to_string(), 17..23));
+        assert_eq!(arms[1].0, Pattern::Var("m".to_string(), 26..27));
+        assert_eq!(sexpr(&arms[1].1), "(f m)");
+        assert_eq!(expr.span(), 0..34);
+    }
+
+    #[test]
+    fn case_arms_end_at_their_column() {
+        parses_to(
+            "case a of\n  _ -> case b of\n    1 -> x\n    _ -> y\n  z -> z",
+            "(case a of Wildcard(12..13) -> (case b of IntLit(1, 31..32) -> x; Wildcard(42..43) -> y); Var(\"z\", 51..52) -> z)",
+        );
+        parses_to("f (case a of x -> x) 1", "((f (case a of Var(\"x\", 13..14) -> x)) 1)");
+    }
+
+    #[test]
+    fn case_arms_line_up_with_tabs_expanded() {
+        let input = "f x = case x of\n\t0 -> 1\n        _ -> 2\n";
+        let (declarations, errors) = parse_declarations(input, 8);
+        assert_eq!(errors, vec![]);
+        let TopLevel::Definition(definition) = &declarations[0] else { panic!("expected a definition") };
+        let Expr::Lam { body, .. } = &definition.def_expr else { panic!("expected a lambda") };
+        let Expr::Case { arms, .. } = &**body else { panic!("expected a case") };
+        assert_eq!(arms.len(), 2);
+        // at four columns the tab no longer reaches the second arm
+        assert_eq!(parse_declarations(input, 4).1.len(), 1);
+    }
+
+    #[test]
+    fn case_needs_of_and_arrows() {
+        assert_eq!(
+            parse_expr("case x\n  1 -> 2"),
+            Err(ParseError::Expected { offset: 11, len: 2, expected: "`of` after the scrutinee".to_string() })
+        );
+        assert_eq!(
+            parse_expr("case x of\n  1 2"),
+            Err(ParseError::Expected { offset: 14, len: 1, expected: "`->` after the pattern".to_string() })
+        );
+        assert_eq!(
+            parse_expr("case x of\n  (y) -> y"),
+            Err(ParseError::Expected { offset: 12, len: 1, expected: "a pattern".to_string() })
+        );
+    }
+
+    #[test]
+    fn broken_definition_does_not_stop_parsing() {
+        let (declarations, errors) = parse_declarations("answer = 42\nbroken = = 3\n  more\nzero = 0\n", DEFAULT_TAB_WIDTH);
+        assert_eq!(declared_names(&declarations), vec!["answer", "zero"]);
+        assert_eq!(
+            errors,
+            vec![ParseError::Expected { offset: 21, len: 1, expected: "an expression".to_string() }]
+        );
+    }
+
+    #[test]
+    fn unlexable_definition_does_not_stop_parsing() {
+        let (declarations, errors) = parse_declarations("answer = 42\nbroken =\n  @ 3\n\nzero = 0\nbad = 1 ? 2", DEFAULT_TAB_WIDTH);
+        assert_eq!(declared_names(&declarations), vec!["answer", "zero"]);
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::UnexpectedChar { offset: 23, found: '@' },
+                ParseError::UnexpectedChar { offset: 45, found: '?' },
+            ]
+        );
+    }
+
+    fn partial_lines(input: &str) -> Vec<PartialExpr<'_>> {
+        split_lines(lex_body(input).unwrap()).into_iter().map(parse_partial).collect()
+    }
+
+    #[test]
+    fn lines_are_sorted_into_partials() {
+        let lines = partial_lines("answer =\n  42\n-- note\n\nzero = 0");
+        let kinds: Vec<_> = lines
+            .iter()
+            .map(|p| match p {
+                PartialExpr::Empty => "empty",
+                PartialExpr::Declarations(_) => "declaration",
+                PartialExpr::Continuation(_) => "continuation",
+            })
+            .collect();
+        assert_eq!(kinds, vec!["declaration", "continuation", "empty", "empty", "declaration"]);
+    }
+
+    #[test]
+    fn continuation_lines_combine_into_one_definition() {
+        let combined = partial_lines("answer =\n  40 +\n\n  2\n")
+            .into_iter()
+            .fold(PartialExpr::Empty, combine_parts);
+        let PartialExpr::Declarations(ref lines) = combined else { panic!("expected declarations") };
+        assert_eq!(lines.len(), 1);
+        let (declarations, errors) = complete_declarations(combined, DEFAULT_TAB_WIDTH);
+        assert_eq!(errors, vec![]);
+        let [TopLevel::Definition(definition)] = &declarations[..] else { panic!("expected one definition") };
+        assert_eq!(definition.name, "answer");
+        assert_eq!(sexpr(&definition.def_expr), "(40 + 2)");
+        assert_eq!(definition.def_expr.span(), 11..20);
+    }
+
+    #[test]
+    fn unindented_lines_start_new_declarations() {
+        let combined = partial_lines("answer =\n  42\nzero = 0\n  + 1")
+            .into_iter()
+            .fold(PartialExpr::Empty, combine_parts);
+        let (declarations, _) = complete_declarations(combined, DEFAULT_TAB_WIDTH);
+        assert_eq!(declared_names(&declarations), vec!["answer", "zero"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn tokens_serialize_with_owned_text() {
+        let tokens = lex_body("foo = \"bar\"").unwrap();
+        assert_eq!(
+            serde_json::to_string(&tokens).unwrap(),
+            concat!(
+                r#"[{"start":0,"end":3,"content":{"Ident":"foo"}},"#,
+                r#"{"start":3,"end":4,"content":{"Space":1}},"#,
+                r#"{"start":4,"end":5,"content":"Equals"},"#,
+                r#"{"start":5,"end":6,"content":{"Space":1}},"#,
+                r#"{"start":6,"end":11,"content":{"String":"bar"}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn lex_iter_is_lazy() {
+        let input = format!("answer = 42{} @", " + 1".repeat(10_000));
+        let mut tokens = lex_iter(&input);
+        let first: Vec<_> = tokens.by_ref().take(2).map(|t| t.unwrap().content).collect();
+        assert_eq!(first, vec![TokenContent::Ident("answer"), TokenContent::Space(1)]);
+        assert_eq!(tokens.offset(), 7);
+    }
+
+    #[test]
+    fn lex_iter_stops_after_an_error() {
+        let mut tokens = lex_iter("x @ y");
+        assert!(tokens.next().unwrap().is_ok());
+        assert!(tokens.next().unwrap().is_ok());
+        assert_eq!(tokens.next(), Some(Err(ParseError::UnexpectedChar { offset: 2, found: '@' })));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn lsp_range_on_the_first_line() {
+        let text = "foo = bar";
+        let tokens = lex_body(text).unwrap();
+        assert_eq!(tokens[4].lsp_range(text), lsp::Range::new(lsp::Position::new(0, 6), lsp::Position::new(0, 9)));
+    }
+
+    #[test]
+    fn lsp_range_is_relative_to_its_line() {
+        let text = "foo\n  bar";
+        let tokens = lex_body(text).unwrap();
+        assert_eq!(tokens[3].lsp_range(text), lsp::Range::new(lsp::Position::new(1, 2), lsp::Position::new(1, 5)));
+    }
+
+    #[test]
+    fn lsp_range_spans_lines() {
+        let text = "x = \"\"\"one\ntwo\"\"\"";
+        let tokens = lex_body(text).unwrap();
+        assert_eq!(tokens[4].content, TokenContent::MultilineString("one\ntwo".to_string()));
+        assert_eq!(tokens[4].lsp_range(text), lsp::Range::new(lsp::Position::new(0, 4), lsp::Position::new(1, 6)));
+    }
+
+    #[test]
+    fn lex_all_tags_tokens_with_their_line() {
+        let tagged = lex_all("x = 1\n{- a\nb -} y\n  z").unwrap();
+        let lines: Vec<_> = tagged
+            .iter()
+            .filter(|(_, t)| is_significant(t))
+            .map(|(line, t)| (*line, t.content.clone()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (0, TokenContent::Ident("x")),
+                (0, TokenContent::Equals),
+                (0, TokenContent::Integer(1)),
+                (2, TokenContent::Ident("y")),
+                (3, TokenContent::Ident("z")),
+            ]
+        );
+        let comment = tagged.iter().find(|(_, t)| matches!(t.content, TokenContent::BlockComment(_)));
+        assert_eq!(comment.map(|(line, _)| *line), Some(1));
+    }
+
+    #[test]
+    fn trivia_is_spacing_and_comments() {
+        let trivia: Vec<_> = lex_body("x {- a -} -- b\n\t1")
+            .unwrap()
+            .iter()
+            .map(|t| t.content.is_trivia())
+            .collect();
+        assert_eq!(trivia, vec![false, true, true, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn significant_tokens_drop_trivia() {
+        let tokens = lex_body("module foo").unwrap();
+        let significant: Vec<_> = significant_tokens(&tokens).into_iter().map(|(i, t)| (i, t.content.clone())).collect();
+        assert_eq!(significant, vec![(0, TokenContent::Module), (2, TokenContent::Ident("foo"))]);
     }
 }
@@ -0,0 +1,183 @@
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::parsing::{self, Token};
+
+/// The byte offset into `line` of a character counted in UTF-16 code units,
+/// as LSP counts them. A character in the middle of a surrogate pair moves
+/// on to the end of the pair.
+fn utf16_to_byte(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line.len()
+}
+
+/// Converts a zero-based line and character into a byte offset into `text`.
+/// Characters are counted in UTF-16 code units, as LSP counts them.
+/// Characters past the end of a line are clamped to the end of that line,
+/// and lines past the end of the document clamp to the end of the document.
+/// Lines may end in either `\n` or `\r\n`.
+pub fn line_col_to_offset(text: &str, line: u32, character: u32) -> usize {
+    let mut offset = 0;
+    for (i, l) in text.split_inclusive('\n').enumerate() {
+        if i == line as usize {
+            let content = l.strip_suffix('\n').unwrap_or(l);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            return offset + utf16_to_byte(content, character);
+        }
+        offset += l.len();
+    }
+    text.len()
+}
+
+/// Converts a byte offset into `text` into a zero-based line and character,
+/// counting characters in UTF-16 code units
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(line as u32, before[line_start..].encode_utf16().count() as u32)
+}
+
+/// A token along with the line and character of its start and end
+#[derive(PartialEq, Clone, Debug)]
+pub struct PositionedToken<'doc> {
+    pub token: Token<'doc>,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl PositionedToken<'_> {
+    pub fn range(&self) -> Range {
+        Range::new(self.start, self.end)
+    }
+}
+
+/// Lexes a whole document, placing each token by line and character.
+/// Declarations that fail to lex are left out so the rest of the document
+/// still gets positions.
+pub fn tokenize_document(text: &str) -> Vec<PositionedToken<'_>> {
+    let (tokens, _) = parsing::lex_recovering(text);
+    // Tokens come in document order, so the position of each one is found
+    // by walking forward from the last rather than from the start of `text`
+    let mut offset = 0;
+    let mut position = Position::new(0, 0);
+    let mut advance_to = |target: usize| {
+        for c in text[offset..target].chars() {
+            if c == '\n' {
+                position = Position::new(position.line + 1, 0);
+            } else {
+                position.character += c.len_utf16() as u32;
+            }
+        }
+        offset = target;
+        position
+    };
+    tokens
+        .into_iter()
+        .map(|token| {
+            let start = advance_to(token.start);
+            let end = advance_to(token.end);
+            PositionedToken { token, start, end }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenContent;
+
+    const DOC: &str = "module Foo where\n\nx = 1\n  longer = \"line\"\n";
+
+    #[test]
+    fn can_convert_line_col_to_offset() {
+        assert_eq!(line_col_to_offset(DOC, 0, 0), 0);
+        assert_eq!(line_col_to_offset(DOC, 0, 7), 7);
+        assert_eq!(line_col_to_offset(DOC, 1, 0), 17);
+        assert_eq!(line_col_to_offset(DOC, 2, 4), 22);
+        assert_eq!(line_col_to_offset(DOC, 3, 2), 26);
+    }
+
+    #[test]
+    fn line_col_past_the_end_is_clamped() {
+        assert_eq!(line_col_to_offset(DOC, 1, 5), 17);
+        assert_eq!(line_col_to_offset(DOC, 2, 100), 23);
+        assert_eq!(line_col_to_offset(DOC, 10, 0), DOC.len());
+    }
+
+    #[test]
+    fn can_convert_offset_to_position() {
+        assert_eq!(offset_to_position(DOC, 0), Position::new(0, 0));
+        assert_eq!(offset_to_position(DOC, 16), Position::new(0, 16));
+        assert_eq!(offset_to_position(DOC, 17), Position::new(1, 0));
+        assert_eq!(offset_to_position(DOC, 22), Position::new(2, 4));
+        assert_eq!(offset_to_position(DOC, 26), Position::new(3, 2));
+    }
+
+    #[test]
+    fn tokenize_document_places_tokens_by_line() {
+        let tokens = tokenize_document(DOC);
+        let x = tokens.iter().find(|t| t.token.content == TokenContent::Ident("x")).unwrap();
+        assert_eq!(x.range(), Range::new(Position::new(2, 0), Position::new(2, 1)));
+        let longer = tokens.iter().find(|t| t.token.content == TokenContent::Ident("longer")).unwrap();
+        assert_eq!(longer.range(), Range::new(Position::new(3, 2), Position::new(3, 8)));
+        let newline = tokens.last().unwrap();
+        assert_eq!(newline.range(), Range::new(Position::new(3, 17), Position::new(4, 0)));
+    }
+
+    #[test]
+    fn tokenize_document_agrees_with_offset_conversion() {
+        for positioned in tokenize_document(DOC) {
+            assert_eq!(positioned.start, offset_to_position(DOC, positioned.token.start));
+            assert_eq!(positioned.end, offset_to_position(DOC, positioned.token.end));
+        }
+    }
+
+    #[test]
+    fn can_convert_positions_on_crlf_lines() {
+        let doc = "module Foo where\r\nx = 1\r\ny = 2";
+        assert_eq!(line_col_to_offset(doc, 1, 4), 22);
+        assert_eq!(offset_to_position(doc, 22), Position::new(1, 4));
+        // The `\r` belongs to the line ending rather than the line
+        assert_eq!(line_col_to_offset(doc, 1, 100), 23);
+        assert_eq!(offset_to_position(doc, 25), Position::new(2, 0));
+    }
+
+    const EMOJI: &str = "smile = \"😀\" ++ é\nnext = 1\n";
+
+    #[test]
+    fn characters_are_counted_in_utf16() {
+        // the emoji is four bytes but two UTF-16 units, and `é` two bytes but one
+        assert_eq!(offset_to_position(EMOJI, 13), Position::new(0, 11));
+        assert_eq!(line_col_to_offset(EMOJI, 0, 11), 13);
+        assert_eq!(offset_to_position(EMOJI, 18), Position::new(0, 16));
+        assert_eq!(line_col_to_offset(EMOJI, 0, 16), 18);
+        // halfway through the emoji moves on to after it
+        assert_eq!(line_col_to_offset(EMOJI, 0, 10), 13);
+        assert_eq!(line_col_to_offset(EMOJI, 0, 100), 20);
+    }
+
+    #[test]
+    fn tokens_after_an_emoji_are_placed_by_utf16() {
+        let tokens = tokenize_document(EMOJI);
+        let op = tokens.iter().find(|t| t.token.content == TokenContent::Operator("++")).unwrap();
+        assert_eq!(op.range(), Range::new(Position::new(0, 13), Position::new(0, 15)));
+        for positioned in &tokens {
+            assert_eq!(positioned.start, offset_to_position(EMOJI, positioned.token.start));
+            assert_eq!(positioned.end, offset_to_position(EMOJI, positioned.token.end));
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        for offset in 0..DOC.len() {
+            let pos = offset_to_position(DOC, offset);
+            assert_eq!(line_col_to_offset(DOC, pos.line, pos.character), offset);
+        }
+    }
+}
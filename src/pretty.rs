@@ -0,0 +1,316 @@
+//! Renders parsed code back into Saffron source. The output parses back into
+//! the same tree, and printing that again gives the same text.
+
+use crate::package::{Definition, Import, Module, ModuleReference};
+use crate::parsing::{fixity_of, Associativity, Expr, Fixity, Pattern};
+
+const INDENT: &str = "  ";
+
+/// How tightly an expression holds together, so it can be parenthesized
+/// wherever something looser isn't allowed. Operators sit at their own
+/// precedence, between these.
+const OPEN: u8 = 0;
+const APPLICATION: u8 = 10;
+const ATOM: u8 = 11;
+
+fn precedence(expr: &Expr, fixities: &[Fixity]) -> u8 {
+    match expr {
+        Expr::IntLit(..) | Expr::FloatLit(..) | Expr::StringLit(..) | Expr::Var(..) | Expr::List(..) | Expr::Tuple(..) => ATOM,
+        Expr::App(..) => APPLICATION,
+        Expr::BinOp(op, ..) => fixity_of(fixities, op).0,
+        // these take in everything to their right
+        Expr::Lam { .. } | Expr::Let { .. } | Expr::If { .. } | Expr::Case { .. } | Expr::Where { .. } => OPEN,
+    }
+}
+
+fn escaped(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::IntLit(value, _) => value.to_string(),
+        Pattern::StringLit(value, _) => escaped(value),
+        Pattern::Var(name, _) => name.clone(),
+        Pattern::Wildcard(_) => "_".to_string(),
+    }
+}
+
+fn list(elements: &[Expr], indent: usize, fixities: &[Fixity]) -> String {
+    elements.iter().map(|e| render(e, OPEN, indent, fixities)).collect::<Vec<_>>().join(", ")
+}
+
+/// `expr` parenthesized if it binds looser than `min`, with operators
+/// binding as `fixities` or the built-in fixities say. Everything stays on
+/// one line except `case` arms, which go on lines of their own further in
+/// than `indent`, the column the current line's code starts at.
+fn render(expr: &Expr, min: u8, indent: usize, fixities: &[Fixity]) -> String {
+    let text = match expr {
+        Expr::IntLit(value, _) => value.to_string(),
+        // `{:?}` keeps the `.0` that tells `1.0` apart from `1`
+        Expr::FloatLit(value, _) => format!("{:?}", value),
+        Expr::StringLit(value, _) => escaped(value),
+        Expr::Var(name, _) => name.clone(),
+        Expr::List(elements, _) => format!("[{}]", list(elements, indent, fixities)),
+        Expr::Tuple(elements, _) => format!("({})", list(elements, indent, fixities)),
+        Expr::App(func, arg) => {
+            format!("{} {}", render(func, APPLICATION, indent, fixities), render(arg, ATOM, indent, fixities))
+        }
+        Expr::BinOp(op, lhs, rhs) => {
+            let (p, assoc) = fixity_of(fixities, op);
+            let (left, right) = match assoc {
+                Associativity::Left => (p, p + 1),
+                Associativity::Right => (p + 1, p),
+                Associativity::None => (p + 1, p + 1),
+            };
+            format!("{} {} {}", render(lhs, left, indent, fixities), op, render(rhs, right, indent, fixities))
+        }
+        Expr::Lam { .. } => {
+            let (params, body) = parameters(expr);
+            format!("\\{} -> {}", params.join(" "), render(body, OPEN, indent, fixities))
+        }
+        Expr::Let { name, bound, body, .. } => {
+            let bound = render(bound, OPEN, indent, fixities);
+            format!("let {} = {} in {}", name, bound, render(body, OPEN, indent, fixities))
+        }
+        Expr::If { cond, then_branch, else_branch, .. } => format!(
+            "if {} then {} else {}",
+            render(cond, OPEN, indent, fixities),
+            render(then_branch, OPEN, indent, fixities),
+            render(else_branch, OPEN, indent, fixities)
+        ),
+        Expr::Case { scrutinee, arms, .. } => {
+            let column = indent + INDENT.len();
+            let mut out = format!("case {} of", render(scrutinee, OPEN, indent, fixities));
+            for (p, body) in arms {
+                let body = render(body, OPEN, column, fixities);
+                out.push_str(&format!("\n{:column$}{} -> {}", "", pattern(p), body, column = column));
+            }
+            out
+        }
+        Expr::Where { body, bindings } => {
+            let column = indent + 2 * INDENT.len();
+            let mut out = format!("{}\n{:indent$}{}where", render(body, OPEN, indent, fixities), "", INDENT, indent = indent);
+            for binding in bindings {
+                let (params, expr) = parameters(&binding.def_expr);
+                let lhs: Vec<_> = [binding.name.as_str()].into_iter().chain(params).collect();
+                let bound = render(expr, OPEN, column, fixities);
+                out.push_str(&format!("\n{:column$}{} = {}", "", lhs.join(" "), bound, column = column));
+            }
+            out
+        }
+    };
+    if precedence(expr, fixities) < min {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+/// The parameters of directly nested lambdas, and the body inside them all
+fn parameters(mut expr: &Expr) -> (Vec<&str>, &Expr) {
+    let mut params = vec![];
+    while let Expr::Lam { param, body, .. } = expr {
+        params.push(param.as_str());
+        expr = body;
+    }
+    (params, expr)
+}
+
+/// The bindings of directly nested `let`s, and the body inside them all
+fn bindings(mut expr: &Expr) -> (Vec<(&str, &Expr)>, &Expr) {
+    let mut bindings = vec![];
+    while let Expr::Let { name, bound, body, .. } = expr {
+        bindings.push((name.as_str(), &**bound));
+        expr = body;
+    }
+    (bindings, expr)
+}
+
+/// The right-hand side of a definition: a `let` gets a line per binding,
+/// anything else stays on the line of the `=`
+fn body(expr: &Expr, fixities: &[Fixity]) -> String {
+    if !matches!(expr, Expr::Let { .. }) {
+        return format!(" {}", render(expr, OPEN, 0, fixities));
+    }
+    let (bindings, body) = bindings(expr);
+    let mut out = String::new();
+    // the bindings line up after the `let `
+    let binding_column = INDENT.len() + 4;
+    for (i, (name, bound)) in bindings.into_iter().enumerate() {
+        let keyword = if i == 0 { "let" } else { "   " };
+        let bound = render(bound, OPEN, binding_column, fixities);
+        out.push_str(&format!("\n{}{} {} = {}", INDENT, keyword, name, bound));
+    }
+    out.push_str(&format!("\n{}in {}", INDENT, render(body, OPEN, INDENT.len(), fixities)));
+    out
+}
+
+fn definition(out: &mut String, definition: &Definition, fixities: &[Fixity]) {
+    if let Some(ty) = &definition.def_type {
+        out.push_str(&format!("{} : {}\n", definition.name, ty.pretty()));
+    }
+    // `f = \x -> body` is written `f x = body`
+    let (params, expr) = parameters(&definition.def_expr);
+    out.push_str(&definition.name);
+    for param in params {
+        out.push(' ');
+        out.push_str(param);
+    }
+    out.push_str(" =");
+    out.push_str(&body(expr, fixities));
+    out.push('\n');
+}
+
+fn import(import: &Import) -> String {
+    let names = match &import.reference {
+        ModuleReference::WildCard => return format!("import {}", import.name.value),
+        ModuleReference::Single(name) => name.name.clone(),
+        ModuleReference::Many(names) => names.iter().map(|n| n.name.as_str()).collect::<Vec<_>>().join(", "),
+    };
+    format!("import {} ({})", import.name.value, names)
+}
+
+fn fixity(fixity: &Fixity) -> String {
+    let keyword = match fixity.assoc {
+        Associativity::Left => "infixl",
+        Associativity::Right => "infixr",
+        Associativity::None => "infix",
+    };
+    format!("{} {} {}", keyword, fixity.precedence, fixity.operator)
+}
+
+impl Expr {
+    /// The expression as source, with only the parentheses it needs given
+    /// the built-in fixities. It's all on one line unless it contains a
+    /// `case`.
+    pub fn pretty(&self) -> String {
+        render(self, OPEN, 0, &[])
+    }
+}
+
+impl Module {
+    /// The module as source: the header, then the imports, then the fixity
+    /// declarations, then each definition below its signature, with a blank
+    /// line between sections. Operators are grouped by the module's fixities.
+    pub fn pretty(&self) -> String {
+        let mut sections = vec![];
+        if self.name_span.is_some() {
+            let exports = match &self.exports {
+                Some(exports) => format!(" ({})", exports.iter().map(|e| e.name.as_str()).collect::<Vec<_>>().join(", ")),
+                None => String::new(),
+            };
+            sections.push(format!("module {}{} where\n", self.name, exports));
+        }
+        if !self.imports.is_empty() {
+            sections.push(self.imports.iter().map(|i| import(i) + "\n").collect());
+        }
+        if !self.fixities.is_empty() {
+            sections.push(self.fixities.iter().map(|f| fixity(f) + "\n").collect());
+        }
+        for member in &self.members {
+            let mut out = String::new();
+            definition(&mut out, member, &self.fixities);
+            sections.push(out);
+        }
+        sections.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::package::Module;
+    use crate::parsing::parse_expr;
+
+    fn pretty(input: &str) -> String {
+        parse_expr(input).unwrap().pretty()
+    }
+
+    #[test]
+    fn only_needed_parentheses_are_kept() {
+        assert_eq!(pretty("(f x) (g y)"), "f x (g y)");
+        assert_eq!(pretty("(a + b) * c"), "(a + b) * c");
+        assert_eq!(pretty("a + (b * c)"), "a + b * c");
+        assert_eq!(pretty("(a - b) - c"), "a - b - c");
+        assert_eq!(pretty("a - (b - c)"), "a - (b - c)");
+        assert_eq!(pretty("a ++ (b ++ c)"), "a ++ b ++ c");
+        assert_eq!(pretty("(\\x -> x) 1"), "(\\x -> x) 1");
+        assert_eq!(pretty("f (let x = 1 in x)"), "f (let x = 1 in x)");
+        assert_eq!(pretty("[(f x), [\\y -> y],]"), "[f x, [\\y -> y]]");
+        assert_eq!(pretty("f ((x), (\\y -> y, ())) (1)"), "f (x, (\\y -> y, ())) 1");
+        assert_eq!(pretty("(if a then b else c) + 1"), "(if a then b else c) + 1");
+        assert_eq!(pretty("f (case x of\n  _ -> y) 1"), "f (case x of\n  _ -> y) 1");
+    }
+
+    #[test]
+    fn literals_print_as_they_lex() {
+        assert_eq!(pretty("1.0"), "1.0");
+        assert_eq!(pretty("-3"), "-3");
+        assert_eq!(pretty(r#""say \"hi\"\n""#), r#""say \"hi\"\n""#);
+    }
+
+    const CANONICAL: &str = "module Main (answer, main) where
+
+import Data.List (map, filter)
+import Data.Map
+
+infixl 5 ++
+infix 4 <>
+
+answer : Int
+answer = 42
+
+twice f x = f (f x)
+
+quadruple x = go (go x)
+  where
+    go y = twice double y
+    double z = z * 2
+
+describe n = case n of
+  0 -> \"none\"
+  1 -> \"one\"
+  _ -> case n > 9 of
+    m -> show m
+
+main =
+  let x = twice (\\y -> y * 2) answer
+      y = x + 1
+  in x ++ y ++ (x <> y) ++ (x ++ y)
+";
+
+    #[test]
+    fn canonical_modules_print_unchanged() {
+        let (module, diagnostics) = Module::parse(CANONICAL);
+        assert!(diagnostics.iter().all(|d| d.message.ends_with("has no type signature")));
+        assert_eq!(module.pretty(), CANONICAL);
+    }
+
+    #[test]
+    fn parentheses_follow_declared_fixities() {
+        let (module, _) = Module::parse("infixr 5 +\nf = (a + b) + c\ng = a + (b + c)\n");
+        assert_eq!(module.pretty(), "infixr 5 +\n\nf = (a + b) + c\n\ng = a + b + c\n");
+    }
+
+    #[test]
+    fn printing_is_stable() {
+        let (module, _) = Module::parse("module Main where\nf = \\x y -> let a = x in a\nmain = (f 1) 2\n");
+        let once = module.pretty();
+        assert_eq!(once, "module Main where\n\nf x y =\n  let a = x\n  in a\n\nmain = f 1 2\n");
+        assert_eq!(Module::parse(&once).0.pretty(), once);
+    }
+}
@@ -0,0 +1,414 @@
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::package::Module;
+use crate::parsing::{Expr, Pattern};
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    /// A list whose elements all have the given type
+    List(Box<Type>),
+    /// The types of each element of a tuple; `()` when there are none
+    Tuple(Vec<Type>),
+    /// A function from the first type to the second
+    Fun(Box<Type>, Box<Type>),
+    /// A type not known yet, to be solved by unification
+    Var(u32),
+}
+
+impl Type {
+    fn fun(param: Type, result: Type) -> Type {
+        Type::Fun(Box::new(param), Box::new(result))
+    }
+
+    /// Whether the type is fully known, with no variables left in it
+    pub fn is_concrete(&self) -> bool {
+        match self {
+            Type::Int | Type::Float | Type::String => true,
+            Type::List(element) => element.is_concrete(),
+            Type::Tuple(elements) => elements.iter().all(Type::is_concrete),
+            Type::Fun(param, result) => param.is_concrete() && result.is_concrete(),
+            Type::Var(_) => false,
+        }
+    }
+
+    /// The types of the arguments a value of this type can be applied to,
+    /// in order; `Int -> String -> Int` takes an `Int` and then a `String`
+    pub fn params(&self) -> Vec<&Type> {
+        let mut params = vec![];
+        let mut ty = self;
+        while let Type::Fun(param, result) = ty {
+            params.push(&**param);
+            ty = result;
+        }
+        params
+    }
+
+    fn mentions(&self, var: u32) -> bool {
+        match self {
+            Type::Int | Type::Float | Type::String => false,
+            Type::List(element) => element.mentions(var),
+            Type::Tuple(elements) => elements.iter().any(|e| e.mentions(var)),
+            Type::Fun(param, result) => param.mentions(var) || result.mentions(var),
+            Type::Var(v) => *v == var,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::List(element) => write!(f, "[{}]", element),
+            Type::Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(Type::to_string).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+            Type::Fun(param, result) if matches!(**param, Type::Fun(..)) => write!(f, "({}) -> {}", param, result),
+            Type::Fun(param, result) => write!(f, "{} -> {}", param, result),
+            Type::Var(v) => write!(f, "t{}", v),
+        }
+    }
+}
+
+/// The state of inference over one module: what each type variable has been
+/// solved to, and the mismatches found so far
+struct Inference {
+    solved: Vec<Option<Type>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Inference {
+    fn new() -> Inference {
+        Inference { solved: vec![], diagnostics: vec![] }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.solved.push(None);
+        Type::Var(self.solved.len() as u32 - 1)
+    }
+
+    /// `ty` with every solved variable replaced by its solution
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match &self.solved[*v as usize] {
+                Some(solution) => self.resolve(solution),
+                None => ty.clone(),
+            },
+            Type::Fun(param, result) => Type::fun(self.resolve(param), self.resolve(result)),
+            Type::List(element) => Type::List(Box::new(self.resolve(element))),
+            Type::Tuple(elements) => Type::Tuple(elements.iter().map(|e| self.resolve(e)).collect()),
+            _ => ty.clone(),
+        }
+    }
+
+    /// Makes the two types equal by solving their variables, or hands back
+    /// both sides when they can't be
+    fn unify(&mut self, left: &Type, right: &Type) -> Result<(), (Type, Type)> {
+        match (self.resolve(left), self.resolve(right)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            // A variable can't stand for a type containing itself
+            (Type::Var(v), other) | (other, Type::Var(v)) if !other.mentions(v) => {
+                self.solved[v as usize] = Some(other);
+                Ok(())
+            }
+            (Type::Fun(p1, r1), Type::Fun(p2, r2)) => {
+                self.unify(&p1, &p2)?;
+                self.unify(&r1, &r2)
+            }
+            (Type::List(e1), Type::List(e2)) => self.unify(&e1, &e2),
+            (Type::Tuple(e1), Type::Tuple(e2)) if e1.len() == e2.len() => {
+                for (a, b) in e1.iter().zip(&e2) {
+                    self.unify(a, b)?;
+                }
+                Ok(())
+            }
+            (left, right) if left == right => Ok(()),
+            mismatch => Err(mismatch),
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr, scope: &mut Vec<(String, Type)>) -> Type {
+        match expr {
+            Expr::IntLit(..) => Type::Int,
+            Expr::FloatLit(..) => Type::Float,
+            Expr::StringLit(..) => Type::String,
+            // every element must have the type of the first
+            Expr::List(elements, _) => {
+                let element_type = self.fresh();
+                for element in elements {
+                    let inferred = self.infer(element, scope);
+                    if let Err((expected, found)) = self.unify(&element_type, &inferred) {
+                        self.diagnostics.push(Diagnostic::error(
+                            element.span(),
+                            format!("expected `{}` but found `{}`", expected, found),
+                        ));
+                    }
+                }
+                Type::List(Box::new(element_type))
+            }
+            Expr::Tuple(elements, _) => Type::Tuple(elements.iter().map(|e| self.infer(e, scope)).collect()),
+            // Anything not in scope comes from elsewhere, so could be anything
+            Expr::Var(name, _) => match scope.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, ty)) => ty.clone(),
+                None => self.fresh(),
+            },
+            Expr::App(func, arg) => {
+                let func_type = self.infer(func, scope);
+                let arg_type = self.infer(arg, scope);
+                let result = self.fresh();
+                match self.resolve(&func_type) {
+                    Type::Fun(param, _) => {
+                        if let Err((expected, found)) = self.unify(&param, &arg_type) {
+                            self.diagnostics.push(Diagnostic::error(
+                                arg.span(),
+                                format!("expected `{}` but found `{}`", expected, found),
+                            ));
+                        }
+                        self.unify(&func_type, &Type::fun(arg_type, result.clone())).ok();
+                    }
+                    Type::Var(_) => {
+                        self.unify(&func_type, &Type::fun(arg_type, result.clone())).ok();
+                    }
+                    not_function => self.diagnostics.push(Diagnostic::error(
+                        func.span(),
+                        format!("`{}` isn't a function, so it can't be applied", not_function),
+                    )),
+                }
+                result
+            }
+            // Operators aren't declared anywhere yet, so only their operands
+            // are checked
+            Expr::BinOp(_, lhs, rhs) => {
+                self.infer(lhs, scope);
+                self.infer(rhs, scope);
+                self.fresh()
+            }
+            // There's no `Bool` yet, so only the branches are checked against
+            // each other
+            Expr::If { cond, then_branch, else_branch, .. } => {
+                self.infer(cond, scope);
+                let then_type = self.infer(then_branch, scope);
+                let else_type = self.infer(else_branch, scope);
+                if let Err((expected, found)) = self.unify(&then_type, &else_type) {
+                    self.diagnostics.push(Diagnostic::error(
+                        else_branch.span(),
+                        format!("expected `{}` but found `{}`", expected, found),
+                    ));
+                }
+                then_type
+            }
+            // every pattern must match the scrutinee, and every arm gives the
+            // same type
+            Expr::Case { scrutinee, arms, .. } => {
+                let scrutinee_type = self.infer(scrutinee, scope);
+                let result = self.fresh();
+                for (pattern, body) in arms {
+                    let bound = match pattern {
+                        Pattern::IntLit(..) => Some(Type::Int),
+                        Pattern::StringLit(..) => Some(Type::String),
+                        Pattern::Var(name, _) => {
+                            scope.push((name.clone(), scrutinee_type.clone()));
+                            None
+                        }
+                        Pattern::Wildcard(_) => None,
+                    };
+                    if let Some(Err((expected, found))) = bound.map(|ty| self.unify(&scrutinee_type, &ty)) {
+                        self.diagnostics.push(Diagnostic::error(
+                            pattern.span(),
+                            format!("expected `{}` but found `{}`", expected, found),
+                        ));
+                    }
+                    let body_type = self.infer(body, scope);
+                    if let Pattern::Var(..) = pattern {
+                        scope.pop();
+                    }
+                    if let Err((expected, found)) = self.unify(&result, &body_type) {
+                        self.diagnostics.push(Diagnostic::error(
+                            body.span(),
+                            format!("expected `{}` but found `{}`", expected, found),
+                        ));
+                    }
+                }
+                result
+            }
+            Expr::Lam { param, body, .. } => {
+                let param_type = self.fresh();
+                scope.push((param.clone(), param_type.clone()));
+                let body_type = self.infer(body, scope);
+                scope.pop();
+                Type::fun(param_type, body_type)
+            }
+            Expr::Let { name, bound, body, .. } => {
+                // bindings can refer to themselves
+                let bound_type = self.fresh();
+                scope.push((name.clone(), bound_type.clone()));
+                let inferred = self.infer(bound, scope);
+                self.unify(&bound_type, &inferred).ok();
+                let body_type = self.infer(body, scope);
+                scope.pop();
+                body_type
+            }
+            Expr::Where { body, bindings } => {
+                // the bindings can refer to each other and to themselves
+                let outer = scope.len();
+                for binding in bindings {
+                    let ty = self.fresh();
+                    scope.push((binding.name.clone(), ty));
+                }
+                for (i, binding) in bindings.iter().enumerate() {
+                    let inferred = self.infer(&binding.def_expr, scope);
+                    let bound_type = scope[outer + i].1.clone();
+                    self.unify(&bound_type, &inferred).ok();
+                }
+                let body_type = self.infer(body, scope);
+                scope.truncate(outer);
+                body_type
+            }
+        }
+    }
+}
+
+/// The type of a single expression, along with any mismatches inside it
+pub fn infer_expr(expr: &Expr) -> (Type, Vec<Diagnostic>) {
+    let mut inference = Inference::new();
+    let ty = inference.infer(expr, &mut vec![]);
+    (inference.resolve(&ty), inference.diagnostics)
+}
+
+/// The type of each of the module's definitions, in order, along with any
+/// mismatches inside them. Definitions can use each other in any order, but
+/// each gets a single type, so one used at two different types is reported
+/// as a mismatch.
+pub fn infer_module(module: &Module) -> (Vec<Type>, Vec<Diagnostic>) {
+    let mut inference = Inference::new();
+    let mut scope: Vec<(String, Type)> = vec![];
+    for definition in &module.members {
+        let ty = inference.fresh();
+        scope.push((definition.name.clone(), ty));
+    }
+    for (i, definition) in module.members.iter().enumerate() {
+        let inferred = inference.infer(&definition.def_expr, &mut scope);
+        if let Err((expected, found)) = inference.unify(&scope[i].1, &inferred) {
+            inference.diagnostics.push(Diagnostic::error(
+                definition.name_span.clone(),
+                format!("`{}` is used as `{}` but defined as `{}`", definition.name, expected, found),
+            ));
+        }
+    }
+    let types = scope.iter().map(|(_, ty)| inference.resolve(ty)).collect();
+    (types, inference.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse_expr;
+
+    fn infer(input: &str) -> (Type, Vec<Diagnostic>) {
+        infer_expr(&parse_expr(input).unwrap())
+    }
+
+    #[test]
+    fn literals_have_their_own_types() {
+        assert_eq!(infer("42"), (Type::Int, vec![]));
+        assert_eq!(infer("2.5"), (Type::Float, vec![]));
+        assert_eq!(infer("\"s\""), (Type::String, vec![]));
+    }
+
+    #[test]
+    fn application_takes_the_result_type() {
+        assert_eq!(infer("(\\x -> x) 42"), (Type::Int, vec![]));
+        assert_eq!(infer("(\\x y -> y) 1 \"s\""), (Type::String, vec![]));
+    }
+
+    #[test]
+    fn list_elements_share_a_type() {
+        assert_eq!(infer("[[1], []]").0.to_string(), "[[Int]]");
+        assert_eq!(
+            infer("[1, \"s\"]").1,
+            vec![Diagnostic::error(4..7, "expected `Int` but found `String`")]
+        );
+    }
+
+    #[test]
+    fn tuples_keep_each_element_type() {
+        assert_eq!(infer("(1, \"s\", ())").0.to_string(), "(Int, String, ())");
+        assert_eq!(
+            infer("[(1, 2), (3, 4, 5)]").1,
+            vec![Diagnostic::error(9..18, "expected `(Int, Int)` but found `(Int, Int, Int)`")]
+        );
+    }
+
+    #[test]
+    fn if_branches_share_a_type() {
+        assert_eq!(infer("if c then 1 else 2"), (Type::Int, vec![]));
+        assert_eq!(
+            infer("if c then 1 else \"s\"").1,
+            vec![Diagnostic::error(17..20, "expected `Int` but found `String`")]
+        );
+    }
+
+    #[test]
+    fn case_patterns_match_the_scrutinee() {
+        assert_eq!(infer("case 1 of\n  0 -> \"zero\"\n  n -> \"other\"").0, Type::String);
+        assert_eq!(infer("\\x -> case x of\n  n -> n").0.to_string(), "t0 -> t0");
+        assert_eq!(
+            infer("case 1 of\n  \"s\" -> 1\n  _ -> 2.5").1,
+            vec![
+                Diagnostic::error(12..15, "expected `Int` but found `String`"),
+                Diagnostic::error(28..31, "expected `Int` but found `Float`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lambdas_are_functions() {
+        let (ty, diagnostics) = infer("\\x -> 42");
+        assert_eq!(ty.to_string(), "t0 -> Int");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn cant_apply_a_non_function() {
+        assert_eq!(
+            infer("42 1").1,
+            vec![Diagnostic::error(0..2, "`Int` isn't a function, so it can't be applied")]
+        );
+    }
+
+    #[test]
+    fn argument_must_match_the_parameter() {
+        assert_eq!(
+            infer("let f = \\x -> x 1 in f \"s\"").1,
+            vec![Diagnostic::error(23..26, "expected `Int -> t2` but found `String`")]
+        );
+    }
+
+    #[test]
+    fn definitions_can_use_each_other() {
+        let (module, _) = Module::parse("main = answer\nanswer = 42\ngreeting = \"hi\"");
+        let (types, diagnostics) = infer_module(&module);
+        assert_eq!(types, vec![Type::Int, Type::Int, Type::String]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn where_bindings_are_inferred_with_the_body() {
+        let (module, _) = Module::parse("f x = pair\n  where\n    pair = (x, half)\n    half = 0.5\nmain = f 1\n");
+        let (types, diagnostics) = infer_module(&module);
+        assert_eq!(types.iter().map(Type::to_string).collect::<Vec<_>>(), vec!["Int -> (Int, Float)", "(Int, Float)"]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn function_types_group_to_the_right() {
+        let nested = Type::fun(Type::fun(Type::Int, Type::Int), Type::fun(Type::String, Type::Float));
+        assert_eq!(nested.to_string(), "(Int -> Int) -> String -> Float");
+    }
+}
@@ -0,0 +1,21 @@
+use std::process::Command;
+
+fn saffron(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_saffron-lang")).args(args).output().unwrap()
+}
+
+#[test]
+fn lex_prints_each_token() {
+    let output = saffron(&["lex", "tests/fixtures/two_modules/Data/List.saffron"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().take(4).collect();
+    assert_eq!(lines, vec!["1:1 Module", "1:8 QualifiedName([\"Data\", \"List\"])", "1:18 Where", "3:1 Ident(\"map\")"]);
+}
+
+#[test]
+fn parse_reports_errors() {
+    let output = saffron(&["parse", "tests/fixtures/broken/Broken.saffron"]);
+    assert!(!output.status.success());
+    assert!(!output.stderr.is_empty());
+}
@@ -0,0 +1,18 @@
+use saffron::{lex_line, TokenContent};
+
+#[test]
+fn can_lex_through_the_library() {
+    let tokens = lex_line("answer = 42").unwrap();
+    let contents: Vec<_> = tokens.into_iter().map(|t| t.content).collect();
+    assert_eq!(
+        contents,
+        vec![
+            TokenContent::Ident("answer"),
+            TokenContent::Space(1),
+            TokenContent::Equals,
+            TokenContent::Space(1),
+            TokenContent::Integer(42),
+            TokenContent::Eof,
+        ]
+    );
+}